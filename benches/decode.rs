@@ -0,0 +1,80 @@
+//! Compares the bit-by-bit decoder against the table decoder.
+//!
+//! Run with `cargo bench --bench decode`, optionally passing a file to decode
+//! instead of the generated text: `cargo bench --bench decode -- test.txt`.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use huffman::encoding::decoding::{decode_data_with_strategy, DecodeStrategy};
+use huffman::encoding::encoding::{generate_prefix_table, get_encoded_data, EncodedData};
+use huffman::encoding::frequency::Freq;
+use huffman::encoding::tree::generate_tree;
+
+/// About 4 MiB of text with a skewed letter distribution, so the codes
+/// have a decent spread of lengths.
+fn generated_input() -> Vec<u8> {
+    let alphabet = b"eeeeeeeeeeeetttttttttaaaaaaaaoooooooiiiiiiinnnnnnnsssssshhhhhhrrrrrrddddlllluuccmmwwffggyyppbbvkjxqz      \n,.";
+    let mut state: u32 = 0x2545_f491;
+    (0..4 << 20)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            alphabet[state as usize % alphabet.len()]
+        })
+        .collect()
+}
+
+fn time_decode(
+    encoded: &EncodedData,
+    prefix_table: &HashMap<u8, (u32, u8)>,
+    strategy: DecodeStrategy,
+) -> (Duration, Vec<u8>) {
+    let mut best = Duration::MAX;
+    let mut output = Vec::new();
+
+    for _ in 0..5 {
+        let start = Instant::now();
+        output = decode_data_with_strategy(
+            &encoded.data,
+            prefix_table.clone(),
+            encoded.symbol_count,
+            strategy,
+        )
+        .unwrap();
+        best = best.min(start.elapsed());
+    }
+
+    (best, output)
+}
+
+fn main() {
+    let input = match std::env::args().skip(1).find(|arg| !arg.starts_with('-')) {
+        Some(path) => std::fs::read(path).unwrap(),
+        None => generated_input(),
+    };
+
+    let mut freq: Freq<u8> = Freq::new();
+    freq.update(&input).unwrap();
+    let prefix_table = generate_prefix_table(generate_tree(&freq)).unwrap();
+    let encoded = get_encoded_data(Cursor::new(&input), prefix_table.clone()).unwrap();
+
+    let (bit_time, bit_output) = time_decode(&encoded, &prefix_table, DecodeStrategy::BitByBit);
+    let (table_time, table_output) = time_decode(&encoded, &prefix_table, DecodeStrategy::Table);
+
+    assert_eq!(bit_output, input);
+    assert_eq!(table_output, input);
+
+    let mib = input.len() as f64 / (1 << 20) as f64;
+    println!("decoding {:.1} MiB", mib);
+    for (name, time) in [("bit by bit", bit_time), ("table", table_time)] {
+        println!(
+            "{:>12}: {:>8.1} ms, {:>7.1} MiB/s",
+            name,
+            time.as_secs_f64() * 1000.0,
+            mib / time.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,880 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use crate::encoding::adaptive::get_adaptive_encoded_data;
+use crate::encoding::canonical::{
+    generate_canonical_prefix_table, get_canonical_header_with_size, get_code_lengths,
+};
+use crate::encoding::crc32::Crc32;
+use crate::encoding::decoding::DecodeStrategy;
+use crate::encoding::encoding::{get_encoded_data, EncodedData};
+use crate::encoding::frequency::Freq;
+use crate::encoding::huffio::{
+    read_prefix_table, write_block_type, write_compressed_data, write_counts_header,
+    write_data_size_header, write_size_header, BlockType, HeaderFormat, Mode,
+};
+use crate::encoding::symbol::{Alphabet, Symbol};
+use crate::encoding::tree::HuffmanTree;
+use crate::error::HuffError;
+use crate::reader::HuffReader;
+use crate::report::CompressionReport;
+use crate::shared_tree::SharedTree;
+use crate::writer::{HuffWriter, BLOCK_SIZE, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+
+/// What an encode did with the input.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EncodeSummary {
+    /// Blocks that were huffman coded.
+    pub huffman_blocks: u64,
+    /// Blocks that were copied as is because coding them would have made them bigger.
+    pub stored_blocks: u64,
+    /// Blocks that were coded with the tree from the block before them.
+    pub reused_blocks: u64,
+    /// Blocks that were coded with an adaptive tree.
+    pub adaptive_blocks: u64,
+    /// Blocks that were coded with the [`Encoder::shared_tree`].
+    pub shared_blocks: u64,
+    /// Sizes, entropy and code lengths for the whole stream.
+    pub report: CompressionReport,
+}
+
+impl EncodeSummary {
+    /// True when there was input and none of it ended up compressed.
+    pub fn stored(&self) -> bool {
+        self.stored_blocks > 0
+            && self.huffman_blocks == 0
+            && self.reused_blocks == 0
+            && self.adaptive_blocks == 0
+            && self.shared_blocks == 0
+    }
+
+    pub(crate) fn add(&mut self, block: WrittenBlock) {
+        match block.block_type {
+            BlockType::Huffman => self.huffman_blocks += 1,
+            BlockType::Stored => self.stored_blocks += 1,
+            BlockType::Reuse => self.reused_blocks += 1,
+            BlockType::Adaptive => self.adaptive_blocks += 1,
+            BlockType::Shared => self.shared_blocks += 1,
+            BlockType::End => {}
+        }
+
+        let report = &mut self.report;
+        report.header_bytes += block.size - block.payload_size;
+        report.payload_bytes += block.payload_size;
+        report.code_bits += block.code_bits;
+        report.padding_bits += block.payload_size * 8 - block.code_bits;
+    }
+}
+
+/// Compresses a reader into the huff format.
+///
+/// The input is split into blocks of [`Encoder::block_size`] bytes, and each
+/// block is coded with a tree built from just its own symbols, so files whose
+/// contents change partway through still compress well. A block whose symbols
+/// look enough like the last one's reuses its tree instead of storing a new
+/// one. The input has to be seekable so its length can go in the file header;
+/// for input that only comes in through `io::Write`, see
+/// [`HuffWriter`](crate::HuffWriter).
+///
+/// By default the input is treated as raw bytes, so anything can be encoded.
+/// Use [`Encoder::alphabet`] to switch to UTF-8 characters for text.
+/// The header holds canonical code lengths unless [`Encoder::header`] says otherwise.
+///
+/// Input that wouldn't get any smaller, like tiny or already compressed files,
+/// is stored as is instead, so the output is never more than a few bytes bigger
+/// than the input.
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    pub(crate) mode: Mode,
+    pub(crate) max_code_length: u8,
+    pub(crate) checksum: bool,
+    pub(crate) block_size: usize,
+    pub(crate) adaptive: bool,
+    pub(crate) shared_tree: Option<SharedTree>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder {
+            mode: Mode::default(),
+            max_code_length: 32,
+            checksum: true,
+            block_size: BLOCK_SIZE,
+            adaptive: false,
+            shared_tree: None,
+        }
+    }
+
+    /// Sets which kind of symbols the input is split into.
+    pub fn alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.mode.alphabet = alphabet;
+        self
+    }
+
+    /// Sets how the code table is stored in the header.
+    pub fn header(mut self, header: HeaderFormat) -> Self {
+        self.mode.header = header;
+        self
+    }
+
+    /// Caps how long a code can get, from 1 to 32 bits. Defaults to 32.
+    ///
+    /// Skewed inputs can produce trees deeper than this; when that happens the
+    /// codes are rebuilt with package-merge, which costs a little compression
+    /// but keeps every code within the limit.
+    pub fn max_code_length(mut self, max_code_length: u8) -> Self {
+        self.max_code_length = max_code_length;
+        self
+    }
+
+    /// Sets whether a CRC-32 of the input is stored so the decoder can check
+    /// its output against it. On by default.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Sets how many bytes of input go in each block, from [`MIN_BLOCK_SIZE`]
+    /// to [`MAX_BLOCK_SIZE`]. Defaults to [`BLOCK_SIZE`].
+    ///
+    /// Smaller blocks follow changes in the input more closely, bigger ones
+    /// spend less on headers. Either way the decoder holds one block in memory
+    /// at a time.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Sets whether blocks are coded with an adaptive tree that's updated after
+    /// every symbol, instead of one built up front. Off by default.
+    ///
+    /// Adaptive blocks don't need a header and the symbols are coded as they're
+    /// read, at the cost of slower coding and somewhat longer codes early on in
+    /// each block. The header format and code length limit don't apply.
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Sets a tree to code blocks with instead of storing one in each file.
+    /// Only its fingerprint goes in the output, and the decoder needs the same
+    /// tree, see [`Decoder::shared_tree`].
+    ///
+    /// The alphabet and header format are taken from the tree, whatever they
+    /// were set to. Every block can use it, and blocks with symbols the tree
+    /// has no code for get a tree of their own instead. Adaptive blocks don't
+    /// use it.
+    pub fn shared_tree(mut self, shared_tree: SharedTree) -> Self {
+        self.shared_tree = Some(shared_tree);
+        self
+    }
+
+    /// Encodes everything from the current position of `input` to the end and
+    /// writes the compressed result to `output`.
+    ///
+    /// Empty input makes a file with no blocks in it. Returns
+    /// [`HuffError::InvalidUtf8`] if the alphabet is [`Alphabet::Chars`] and
+    /// the input isn't valid UTF-8.
+    pub fn encode<R: Read + Seek, W: Write>(
+        &self,
+        input: &mut R,
+        mut output: W,
+    ) -> Result<EncodeSummary, HuffError> {
+        let start = input.stream_position()?;
+        let original_length = input.seek(SeekFrom::End(0))? - start;
+        input.seek(SeekFrom::Start(start))?;
+
+        let mut writer =
+            HuffWriter::with_encoder(&mut output, self.clone()).original_length(original_length);
+        io::copy(input, &mut writer)?;
+        writer.try_finish()?;
+        Ok(writer.summary())
+    }
+
+    /// Encodes an in-memory buffer.
+    pub fn encode_bytes(&self, data: &[u8]) -> Result<Vec<u8>, HuffError> {
+        let mut output = Vec::new();
+        self.encode(&mut Cursor::new(data), &mut output)?;
+        Ok(output)
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        match &self.shared_tree {
+            Some(shared_tree) => shared_tree.mode,
+            None => self.mode,
+        }
+    }
+
+    pub(crate) fn check_block_size(&self) -> Result<(), HuffError> {
+        match self.block_size {
+            MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE => Ok(()),
+            size => Err(HuffError::InvalidBlockSize(size)),
+        }
+    }
+}
+
+/// What [`write_block`] wrote, for the [`CompressionReport`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WrittenBlock {
+    pub(crate) block_type: BlockType,
+    /// The whole block, type byte included.
+    pub(crate) size: u64,
+    /// The coded data, or the raw bytes of a stored block.
+    pub(crate) payload_size: u64,
+    /// Bits of the payload that are codes rather than padding.
+    pub(crate) code_bits: u64,
+}
+
+/// Writes one block holding `input`, whichever of these comes out smallest:
+/// coded with a new tree built from `freq`, coded with the tree from
+/// `previous_header`, coded with the encoder's shared tree, or stored as is.
+/// Adaptive encoders pick between an adaptive block and a stored one instead.
+/// Returns what was written.
+///
+/// `freq` has to cover every symbol in `input`, and the input is also fed to
+/// `checksum`. When a new tree is written its header replaces `previous_header`.
+pub(crate) fn write_block<S: Symbol, W: Write>(
+    freq: &Freq<S>,
+    input: &[u8],
+    mut output: W,
+    encoder: &Encoder,
+    checksum: &mut Crc32,
+    previous_header: &mut Option<Vec<u8>>,
+) -> Result<WrittenBlock, HuffError> {
+    checksum.update(input);
+    let stored_size = 8 + input.len() as u64;
+
+    if encoder.adaptive {
+        let encoded = get_adaptive_encoded_data::<S>(input)?;
+        if stored_size <= 24 + encoded.data.len() as u64 {
+            return write_stored_block(input, output);
+        }
+        write_block_type(&mut output, BlockType::Adaptive)?;
+        return write_encoded_data(output, BlockType::Adaptive, 1, encoded);
+    }
+
+    let mode = encoder.mode();
+    let (header, prefix_table) = build_header(freq, mode.header, encoder.max_code_length)?;
+    let header_size = header.len() as u32;
+
+    // the type byte is the same either way, so just compare what comes after it:
+    // the header size and header, then the counts and data size, then the data
+    let huffman_size = 4 + header_size as u64 + 24 + coded_size(freq, &prefix_table);
+
+    // trees the decoder already has, so using one doesn't cost a header
+    let mut known_tables = Vec::new();
+    if let Some(previous_header) = previous_header {
+        let table = read_prefix_table::<S>(mode.header, previous_header)?;
+        known_tables.push((BlockType::Reuse, table));
+    }
+    if let Some(shared_tree) = &encoder.shared_tree {
+        known_tables.push((BlockType::Shared, shared_tree.prefix_table::<S>()?));
+    }
+    // a tree only works if it has a code for every symbol in this block
+    let known = known_tables
+        .into_iter()
+        .filter(|(_, table)| freq.counter.keys().all(|symbol| table.contains_key(symbol)))
+        .map(|(block_type, table)| (24 + coded_size(freq, &table), block_type, table))
+        .min_by_key(|&(size, ..)| size);
+
+    if stored_size <= huffman_size && known.as_ref().is_none_or(|&(size, ..)| stored_size <= size) {
+        return write_stored_block(input, output);
+    }
+
+    let (block_type, header_size, prefix_table) = match known {
+        Some((size, block_type, table)) if size <= huffman_size => {
+            write_block_type(&mut output, block_type)?;
+            (block_type, 0, table)
+        }
+        _ => {
+            write_block_type(&mut output, BlockType::Huffman)?;
+            write_size_header(&mut output, header_size)?;
+            write_compressed_data(&mut output, header.clone())?;
+            *previous_header = Some(header);
+            (BlockType::Huffman, 4 + header_size as u64, prefix_table)
+        }
+    };
+
+    let encoded = get_encoded_data(input, prefix_table)?;
+    write_encoded_data(output, block_type, 1 + header_size, encoded)
+}
+
+type PrefixTable<S> = HashMap<S, (u32, u8)>;
+
+/// Builds the tree for `freq` and returns its header and prefix table.
+pub(crate) fn build_header<S: Symbol>(
+    freq: &Freq<S>,
+    header_format: HeaderFormat,
+    max_code_length: u8,
+) -> Result<(Vec<u8>, PrefixTable<S>), HuffError> {
+    let tree = HuffmanTree::with_max_length(freq, max_code_length)?;
+    match header_format {
+        HeaderFormat::Tree => Ok((tree.header_with_size()?.1, tree.prefix_table()?)),
+        HeaderFormat::Canonical => {
+            let code_lengths = get_code_lengths(&tree.prefix_table()?);
+            Ok((
+                get_canonical_header_with_size(&code_lengths)?.1,
+                generate_canonical_prefix_table(&code_lengths)?,
+            ))
+        }
+    }
+}
+
+fn write_stored_block<W: Write>(input: &[u8], mut output: W) -> Result<WrittenBlock, HuffError> {
+    write_block_type(&mut output, BlockType::Stored)?;
+    write_data_size_header(&mut output, input.len() as u64)?;
+    write_compressed_data(&mut output, input.to_vec())?;
+    Ok(WrittenBlock {
+        block_type: BlockType::Stored,
+        size: 1 + 8 + input.len() as u64,
+        payload_size: input.len() as u64,
+        code_bits: input.len() as u64 * 8,
+    })
+}
+
+/// Writes the counts, data size and data that end every coded block.
+/// `written` is how much of the block went out before them.
+fn write_encoded_data<W: Write>(
+    mut output: W,
+    block_type: BlockType,
+    written: u64,
+    encoded: EncodedData,
+) -> Result<WrittenBlock, HuffError> {
+    let payload_size = encoded.data.len() as u64;
+    write_counts_header(&mut output, encoded.symbol_count, encoded.byte_count)?;
+    write_data_size_header(&mut output, payload_size)?;
+    write_compressed_data(&mut output, encoded.data)?;
+    Ok(WrittenBlock {
+        block_type,
+        size: written + 24 + payload_size,
+        payload_size,
+        code_bits: payload_size * 8 - encoded.padding_bits as u64,
+    })
+}
+
+/// How many bytes the symbols counted in `freq` take up once coded with `prefix_table`.
+fn coded_size<S: Symbol>(freq: &Freq<S>, prefix_table: &HashMap<S, (u32, u8)>) -> u64 {
+    let bits: u64 = freq
+        .counter
+        .iter()
+        .map(|(symbol, &count)| count * prefix_table[symbol].1 as u64)
+        .sum();
+    bits.div_ceil(8)
+}
+
+/// Decompresses data produced by an [`Encoder`].
+///
+/// Codes are looked up a whole table entry at a time by default; see
+/// [`Decoder::strategy`] to switch to the slower bit-by-bit decoder.
+#[derive(Debug, Default, Clone)]
+pub struct Decoder {
+    pub(crate) strategy: DecodeStrategy,
+    pub(crate) shared_tree: Option<SharedTree>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            strategy: DecodeStrategy::default(),
+            shared_tree: None,
+        }
+    }
+
+    /// Sets how codes are looked up while decoding. The output is the same either way.
+    pub fn strategy(mut self, strategy: DecodeStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets the tree for input that was coded with [`Encoder::shared_tree`].
+    /// Decoding such input fails without it, or if the fingerprint in the
+    /// input doesn't match.
+    pub fn shared_tree(mut self, shared_tree: SharedTree) -> Self {
+        self.shared_tree = Some(shared_tree);
+        self
+    }
+
+    /// Reads compressed data from `input` and writes the original data to `output`.
+    pub fn decode<R: Read, W: Write>(&self, input: &mut R, mut output: W) -> Result<(), HuffError> {
+        let mut reader = HuffReader::with_decoder(input, self.clone());
+        io::copy(&mut reader, &mut output)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Decodes an in-memory buffer.
+    pub fn decode_bytes(&self, data: &[u8]) -> Result<Vec<u8>, HuffError> {
+        let mut output = Vec::new();
+        self.decode(&mut Cursor::new(data), &mut output)?;
+        Ok(output)
+    }
+}
+
+/// Compresses `data` with the default [`Encoder`].
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, HuffError> {
+    Encoder::new().encode_bytes(data)
+}
+
+/// Decompresses `data` with the default [`Decoder`].
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, HuffError> {
+    Decoder::new().decode_bytes(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::huffio::read_file_header;
+    use crate::encoding::test_cases;
+
+    use super::*;
+
+    #[test]
+    fn test_compress_decompress_sample() {
+        let input = test_cases::SAMPLE_TEST.as_bytes();
+
+        let compressed = compress(input).unwrap();
+        let decompressed = decompress(&compressed).unwrap();
+
+        assert_eq!(decompressed, input);
+    }
+
+    #[test]
+    fn test_compress_decompress_unicode() {
+        let input = "привет, мир! Les Misérables".as_bytes();
+
+        let compressed = compress(input).unwrap();
+        let decompressed = decompress(&compressed).unwrap();
+
+        assert_eq!(decompressed, input);
+    }
+
+    #[test]
+    fn test_encode_from_current_position() {
+        let mut input = Cursor::new(test_cases::CAPITAL_TEST.as_bytes());
+        input.seek(SeekFrom::Start(2)).unwrap();
+        let mut compressed = Vec::new();
+
+        Encoder::new().encode(&mut input, &mut compressed).unwrap();
+        let decompressed = Decoder::new().decode_bytes(&compressed).unwrap();
+
+        assert_eq!(decompressed, &test_cases::CAPITAL_TEST.as_bytes()[2..]);
+    }
+
+    #[test]
+    fn test_compress_empty_input() {
+        for alphabet in [Alphabet::Chars, Alphabet::Bytes] {
+            let compressed = Encoder::new().alphabet(alphabet).encode_bytes(&[]).unwrap();
+
+            assert!(decompress(&compressed).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_decompress_garbage() {
+        let result = decompress(&[0x00, 0xde, 0xad, 0xbe, 0xef, 0x01]);
+
+        assert!(matches!(result, Err(HuffError::NotHuff)));
+    }
+
+    #[test]
+    fn test_compress_decompress_binary() {
+        let input: Vec<u8> = (0..=255u8).chain([0xff, 0xfe, 0x00, 0x80]).collect();
+
+        let compressed = compress(&input).unwrap();
+        let decompressed = decompress(&compressed).unwrap();
+
+        assert_eq!(decompressed, input);
+    }
+
+    #[test]
+    fn test_chars_round_trip() {
+        let input = "привет, мир! Les Misérables".as_bytes();
+        let encoder = Encoder::new().alphabet(Alphabet::Chars);
+
+        let compressed = encoder.encode_bytes(input).unwrap();
+        let decompressed = decompress(&compressed).unwrap();
+
+        assert_eq!(
+            read_file_header(&mut compressed.as_slice())
+                .unwrap()
+                .mode
+                .alphabet,
+            Alphabet::Chars
+        );
+        assert_eq!(decompressed, input);
+    }
+
+    #[test]
+    fn test_chars_rejects_invalid_utf8() {
+        let encoder = Encoder::new().alphabet(Alphabet::Chars);
+
+        let result = encoder.encode_bytes(&[b'a', 0xff, b'b']);
+
+        assert!(matches!(result, Err(HuffError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn test_tree_header_round_trip() {
+        for alphabet in [Alphabet::Chars, Alphabet::Bytes] {
+            let input = test_cases::CAPITAL_TEST.as_bytes();
+            let encoder = Encoder::new().alphabet(alphabet).header(HeaderFormat::Tree);
+
+            let compressed = encoder.encode_bytes(input).unwrap();
+            let decompressed = decompress(&compressed).unwrap();
+
+            assert_eq!(decompressed, input);
+        }
+    }
+
+    #[test]
+    fn test_canonical_header_is_smaller() {
+        let input = test_cases::SAMPLE_TEST.as_bytes();
+        let encoder = Encoder::new().alphabet(Alphabet::Chars);
+
+        let as_tree = encoder
+            .clone()
+            .header(HeaderFormat::Tree)
+            .encode_bytes(input);
+        let as_canonical = encoder.header(HeaderFormat::Canonical).encode_bytes(input);
+
+        assert!(as_canonical.unwrap().len() < as_tree.unwrap().len());
+    }
+
+    #[test]
+    fn test_fibonacci_input_with_max_code_length() {
+        // symbol i shows up fib(i) times, which makes a tree 19 levels deep
+        let mut input = Vec::new();
+        let (mut a, mut b) = (1, 1);
+        for symbol in 0..20u8 {
+            input.extend(std::iter::repeat_n(symbol, a));
+            (a, b) = (b, a + b);
+        }
+
+        for header in [HeaderFormat::Tree, HeaderFormat::Canonical] {
+            let encoder = Encoder::new().header(header).max_code_length(12);
+
+            let compressed = encoder.encode_bytes(&input).unwrap();
+            let decompressed = decompress(&compressed).unwrap();
+
+            assert_eq!(decompressed, input);
+        }
+    }
+
+    #[test]
+    fn test_decode_strategies_agree() {
+        let input = test_cases::CAPITAL_TEST.as_bytes();
+        let compressed = compress(input).unwrap();
+
+        for strategy in [DecodeStrategy::BitByBit, DecodeStrategy::Table] {
+            let decompressed = Decoder::new()
+                .strategy(strategy)
+                .decode_bytes(&compressed)
+                .unwrap();
+
+            assert_eq!(decompressed, input);
+        }
+    }
+
+    #[test]
+    fn test_max_code_length_too_small() {
+        let encoder = Encoder::new().max_code_length(2);
+
+        let result = encoder.encode_bytes("abcde".as_bytes());
+
+        assert!(matches!(result, Err(HuffError::MaxCodeLengthTooSmall(2))));
+    }
+
+    #[test]
+    fn test_bytes_header_is_smaller() {
+        let input = test_cases::SAMPLE_TEST.as_bytes();
+
+        let encoder = Encoder::new().header(HeaderFormat::Tree);
+
+        let as_bytes = encoder
+            .clone()
+            .alphabet(Alphabet::Bytes)
+            .encode_bytes(input);
+        let as_chars = encoder.alphabet(Alphabet::Chars).encode_bytes(input);
+
+        assert!(as_bytes.unwrap().len() < as_chars.unwrap().len());
+    }
+
+    #[test]
+    fn test_flipped_bit_fails_checksum() {
+        // two symbols with one bit codes, so any flipped data bit still decodes
+        let input = "ab".repeat(100);
+        let mut compressed = compress(input.as_bytes()).unwrap();
+
+        // skip the trailer and the end block
+        let last_data_byte = compressed.len() - 4 - 1 - 1;
+        compressed[last_data_byte] ^= 0b1000_0000;
+
+        assert!(matches!(
+            decompress(&compressed),
+            Err(HuffError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_incompressible_input_is_stored() {
+        // every byte once, so there's nothing for the codes to save
+        let input: Vec<u8> = (0..=255u8).collect();
+        let mut compressed = Vec::new();
+
+        let summary = Encoder::new()
+            .encode(&mut Cursor::new(&input), &mut compressed)
+            .unwrap();
+
+        assert!(summary.stored());
+        // file header, stored block header, end block and trailer
+        assert_eq!(compressed.len(), input.len() + 14 + 9 + 1 + 4);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_tiny_input_is_stored() {
+        for alphabet in [Alphabet::Chars, Alphabet::Bytes] {
+            let input = "hé".as_bytes();
+            let mut compressed = Vec::new();
+
+            let summary = Encoder::new()
+                .alphabet(alphabet)
+                .encode(&mut Cursor::new(input), &mut compressed)
+                .unwrap();
+
+            assert!(summary.stored());
+            assert_eq!(decompress(&compressed).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_compressible_input_is_not_stored() {
+        let mut input = Cursor::new(test_cases::SAMPLE_TEST.as_bytes());
+
+        let summary = Encoder::new().encode(&mut input, Vec::new()).unwrap();
+
+        assert_eq!(
+            summary,
+            EncodeSummary {
+                huffman_blocks: 1,
+                stored_blocks: 0,
+                reused_blocks: 0,
+                adaptive_blocks: 0,
+                shared_blocks: 0,
+                report: summary.report,
+            }
+        );
+    }
+
+    #[test]
+    fn test_block_size_out_of_range() {
+        for block_size in [0, MIN_BLOCK_SIZE - 1, MAX_BLOCK_SIZE + 1] {
+            let encoder = Encoder::new().block_size(block_size);
+
+            let result = encoder.encode_bytes(b"abc");
+
+            assert!(matches!(result, Err(HuffError::InvalidBlockSize(size)) if size == block_size));
+        }
+    }
+
+    #[test]
+    fn test_tree_per_block() {
+        // text, then a run of binary, then text again
+        let text = test_cases::SAMPLE_TEST.repeat(MIN_BLOCK_SIZE / test_cases::SAMPLE_TEST.len());
+        let mut input = text.as_bytes().to_vec();
+        input.extend((0..MIN_BLOCK_SIZE).map(|i| (i * 7 % 251) as u8 | 0x80));
+        input.extend(text.as_bytes());
+        let mut compressed = Vec::new();
+
+        let summary = Encoder::new()
+            .block_size(MIN_BLOCK_SIZE)
+            .encode(&mut Cursor::new(&input), &mut compressed)
+            .unwrap();
+
+        assert_eq!(summary.huffman_blocks, 3);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_similar_blocks_reuse_tree() {
+        let input =
+            test_cases::CAPITAL_TEST.repeat(MIN_BLOCK_SIZE * 4 / test_cases::CAPITAL_TEST.len());
+
+        for alphabet in [Alphabet::Chars, Alphabet::Bytes] {
+            for header in [HeaderFormat::Tree, HeaderFormat::Canonical] {
+                let encoder = Encoder::new()
+                    .alphabet(alphabet)
+                    .header(header)
+                    .block_size(MIN_BLOCK_SIZE);
+                let mut compressed = Vec::new();
+
+                let summary = encoder
+                    .encode(&mut Cursor::new(input.as_bytes()), &mut compressed)
+                    .unwrap();
+
+                assert_eq!(summary.huffman_blocks, 1);
+                assert_eq!(summary.reused_blocks, 3);
+                assert_eq!(decompress(&compressed).unwrap(), input.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaptive_round_trip() {
+        let input =
+            test_cases::SAMPLE_TEST.repeat(MIN_BLOCK_SIZE * 2 / test_cases::SAMPLE_TEST.len());
+
+        for alphabet in [Alphabet::Chars, Alphabet::Bytes] {
+            let encoder = Encoder::new()
+                .alphabet(alphabet)
+                .adaptive(true)
+                .block_size(MIN_BLOCK_SIZE);
+            let mut compressed = Vec::new();
+
+            let summary = encoder
+                .encode(&mut Cursor::new(input.as_bytes()), &mut compressed)
+                .unwrap();
+
+            assert_eq!(summary.adaptive_blocks, 2);
+            assert_eq!(summary.huffman_blocks + summary.reused_blocks, 0);
+            assert!(compressed.len() < input.len() * 2 / 3);
+            assert_eq!(decompress(&compressed).unwrap(), input.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_adaptive_falls_back_to_stored() {
+        let input: Vec<u8> = (0..=255u8).collect();
+        let mut compressed = Vec::new();
+
+        let summary = Encoder::new()
+            .adaptive(true)
+            .encode(&mut Cursor::new(&input), &mut compressed)
+            .unwrap();
+
+        assert!(summary.stored());
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_without_checksum() {
+        let input = test_cases::SAMPLE_TEST.as_bytes();
+
+        let with_checksum = Encoder::new().encode_bytes(input).unwrap();
+        let without_checksum = Encoder::new().checksum(false).encode_bytes(input).unwrap();
+
+        assert_eq!(without_checksum.len() + 4, with_checksum.len());
+        assert_eq!(decompress(&without_checksum).unwrap(), input);
+    }
+
+    fn sample_shared_tree() -> SharedTree {
+        let mut freq: Freq<u8> = Freq::new();
+        freq.update(test_cases::SAMPLE_TEST.as_bytes()).unwrap();
+        SharedTree::from_freq(&freq, HeaderFormat::Canonical, 32).unwrap()
+    }
+
+    #[test]
+    fn test_shared_tree_round_trip() {
+        let tree = sample_shared_tree();
+        let input = &test_cases::SAMPLE_TEST.as_bytes()[..200];
+        let mut compressed = Vec::new();
+
+        let summary = Encoder::new()
+            .shared_tree(tree.clone())
+            .encode(&mut Cursor::new(input), &mut compressed)
+            .unwrap();
+
+        assert_eq!(summary.shared_blocks, 1);
+        let header = read_file_header(&mut compressed.as_slice()).unwrap();
+        assert_eq!(header.shared_tree, Some(tree.fingerprint()));
+        assert!(compressed.len() < Encoder::new().encode_bytes(input).unwrap().len());
+        let decoder = Decoder::new().shared_tree(tree);
+        assert_eq!(decoder.decode_bytes(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_shared_tree_after_block_with_own_tree() {
+        // the middle block has symbols the shared tree doesn't, which mustn't
+        // stop the last block from going back to it
+        let tree = sample_shared_tree();
+        let sample =
+            test_cases::SAMPLE_TEST.repeat(MIN_BLOCK_SIZE / test_cases::SAMPLE_TEST.len() + 1);
+        let input = [
+            &sample.as_bytes()[..MIN_BLOCK_SIZE],
+            "{}~|".repeat(MIN_BLOCK_SIZE / 4).as_bytes(),
+            &sample.as_bytes()[..MIN_BLOCK_SIZE],
+        ]
+        .concat();
+        let mut compressed = Vec::new();
+
+        let summary = Encoder::new()
+            .block_size(MIN_BLOCK_SIZE)
+            .shared_tree(tree.clone())
+            .encode(&mut Cursor::new(&input), &mut compressed)
+            .unwrap();
+
+        assert_eq!(summary.shared_blocks, 2);
+        assert_eq!(summary.huffman_blocks, 1);
+        let decoder = Decoder::new().shared_tree(tree);
+        assert_eq!(decoder.decode_bytes(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_shared_tree_takes_alphabet_from_tree() {
+        let tree = sample_shared_tree();
+
+        let compressed = Encoder::new()
+            .alphabet(Alphabet::Chars)
+            .header(HeaderFormat::Tree)
+            .shared_tree(tree.clone())
+            .encode_bytes(b"a man")
+            .unwrap();
+
+        let header = read_file_header(&mut compressed.as_slice()).unwrap();
+        assert_eq!(header.mode.alphabet, Alphabet::Bytes);
+        assert_eq!(header.mode.header, HeaderFormat::Canonical);
+    }
+
+    #[test]
+    fn test_shared_tree_missing_symbols() {
+        // the tree has no code for these, so the block gets its own tree
+        let tree = sample_shared_tree();
+        let input = "{}~|".repeat(100);
+
+        let compressed = Encoder::new()
+            .shared_tree(tree.clone())
+            .encode_bytes(input.as_bytes())
+            .unwrap();
+
+        let decoder = Decoder::new().shared_tree(tree);
+        assert_eq!(decoder.decode_bytes(&compressed).unwrap(), input.as_bytes());
+    }
+
+    #[test]
+    fn test_shared_tree_required_to_decode() {
+        let tree = sample_shared_tree();
+        let compressed = Encoder::new()
+            .shared_tree(tree.clone())
+            .encode_bytes(b"a plan")
+            .unwrap();
+
+        let fingerprint = tree.fingerprint();
+        let result = decompress(&compressed);
+
+        assert!(matches!(result, Err(HuffError::MissingSharedTree(f)) if f == fingerprint));
+
+        let mut freq: Freq<u8> = Freq::new();
+        freq.update(b"something else entirely").unwrap();
+        let other = SharedTree::from_freq(&freq, HeaderFormat::Canonical, 32).unwrap();
+        let result = Decoder::new().shared_tree(other).decode_bytes(&compressed);
+        assert!(matches!(
+            result,
+            Err(HuffError::SharedTreeMismatch { expected, .. }) if expected == fingerprint
+        ));
+    }
+}
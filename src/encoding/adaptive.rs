@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use super::bitreader::BitReader;
+use super::bitwriter::BitWriter;
+use super::encoding::EncodedData;
+use super::symbol::Symbol;
+use crate::error::HuffError;
+
+/// One node of an [`AdaptiveTree`]. Leaves have a symbol, except for the
+/// not-yet-transmitted leaf, which stands in for every symbol not seen yet.
+#[derive(Debug, Clone)]
+struct AdaptiveNode<S> {
+    symbol: Option<S>,
+    weight: u64,
+    parent: Option<usize>,
+    left: Option<usize>,
+    right: Option<usize>,
+    // where the node is in `AdaptiveTree::order`
+    number: usize,
+}
+
+/// A Huffman tree that's updated after every symbol (FGK), so the encoder and
+/// decoder can build the same codes as they go without a header or a first
+/// pass to count symbols.
+///
+/// A symbol that hasn't come up yet is sent as the code for the
+/// not-yet-transmitted leaf followed by the symbol's raw `S::BITS` bits.
+#[derive(Debug, Clone)]
+pub struct AdaptiveTree<S> {
+    nodes: Vec<AdaptiveNode<S>>,
+    // node indices from the root down, so weights never go up along it and
+    // siblings are always next to each other
+    order: Vec<usize>,
+    // for each weight, where in `order` the first node with it is
+    leaders: HashMap<u64, usize>,
+    leaves: HashMap<S, usize>,
+    nyt: usize,
+    // reused between calls to collect a code from the leaf up
+    path: Vec<u8>,
+}
+
+impl<S: Symbol> Default for AdaptiveTree<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Symbol> AdaptiveTree<S> {
+    /// A tree with nothing in it but the not-yet-transmitted leaf.
+    pub fn new() -> Self {
+        AdaptiveTree {
+            nodes: vec![AdaptiveNode {
+                symbol: None,
+                weight: 0,
+                parent: None,
+                left: None,
+                right: None,
+                number: 0,
+            }],
+            order: vec![0],
+            leaders: HashMap::from([(0, 0)]),
+            leaves: HashMap::new(),
+            nyt: 0,
+            path: Vec::new(),
+        }
+    }
+
+    /// Writes the code for `symbol` and updates the tree to account for it.
+    pub fn encode(&mut self, symbol: S, bw: &mut BitWriter) {
+        match self.leaves.get(&symbol) {
+            Some(&leaf) => {
+                self.write_path(leaf, bw);
+                self.update(leaf);
+            }
+            None => {
+                self.write_path(self.nyt, bw);
+                bw.write_bits(symbol.to_bits(), S::BITS);
+                let leaf = self.add_symbol(symbol);
+                self.update(leaf);
+            }
+        }
+    }
+
+    /// Reads the code for one symbol and updates the tree to account for it.
+    pub fn decode(&mut self, br: &mut BitReader) -> Result<S, HuffError> {
+        let mut node = self.order[0];
+        while let (Some(left), Some(right)) = (self.nodes[node].left, self.nodes[node].right) {
+            node = match br.next().ok_or(HuffError::TruncatedData)? {
+                0 => left,
+                _ => right,
+            };
+        }
+
+        let leaf = match self.nodes[node].symbol {
+            Some(_) => node,
+            None => {
+                let symbol = S::from_bits(br.read_value(S::BITS)?)?;
+                // a symbol that's already in the tree should have been sent with its code
+                if self.leaves.contains_key(&symbol) {
+                    return Err(HuffError::DuplicateSymbol(symbol.to_bits()));
+                }
+                self.add_symbol(symbol)
+            }
+        };
+        let symbol = self.nodes[leaf].symbol.unwrap();
+        self.update(leaf);
+        Ok(symbol)
+    }
+
+    /// Writes the path from the root down to `node`, 0 for left and 1 for right.
+    fn write_path(&mut self, node: usize, bw: &mut BitWriter) {
+        self.path.clear();
+        let mut node = node;
+        while let Some(parent) = self.nodes[node].parent {
+            let bit = (self.nodes[parent].right == Some(node)) as u8;
+            self.path.push(bit);
+            node = parent;
+        }
+        for &bit in self.path.iter().rev() {
+            bw.write_bits(bit as u32, 1);
+        }
+    }
+
+    /// Splits the not-yet-transmitted leaf into an internal node with a new
+    /// not-yet-transmitted leaf on the left and `symbol` on the right.
+    /// Returns the index of the new leaf.
+    fn add_symbol(&mut self, symbol: S) -> usize {
+        let parent = self.nyt;
+        let leaf = self.push(Some(symbol), parent);
+        let nyt = self.push(None, parent);
+        self.nodes[parent].left = Some(nyt);
+        self.nodes[parent].right = Some(leaf);
+        self.nyt = nyt;
+        self.leaves.insert(symbol, leaf);
+        leaf
+    }
+
+    fn push(&mut self, symbol: Option<S>, parent: usize) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(AdaptiveNode {
+            symbol,
+            weight: 0,
+            parent: Some(parent),
+            left: None,
+            right: None,
+            number: self.order.len(),
+        });
+        self.order.push(index);
+        index
+    }
+
+    /// Bumps the weight of `node` and everything above it. Before each bump the
+    /// node is swapped with the first node of the same weight in `order`, which
+    /// keeps the weights sorted and so keeps the tree a Huffman tree.
+    fn update(&mut self, node: usize) {
+        let mut node = Some(node);
+        while let Some(current) = node {
+            let weight = self.nodes[current].weight;
+            let leader = self.order[self.leaders[&weight]];
+            if leader != current && Some(leader) != self.nodes[current].parent {
+                self.swap(current, leader);
+            }
+
+            self.bump(current);
+            node = self.nodes[current].parent;
+        }
+    }
+
+    /// Adds one to the weight of `node`, moving the leaders of the weight it
+    /// leaves and the one it joins to match.
+    fn bump(&mut self, node: usize) {
+        let number = self.nodes[node].number;
+        let weight = self.nodes[node].weight;
+
+        if self.leaders[&weight] == number {
+            // the node after this one can be a child that was just bumped while
+            // its parent, this node, hasn't been yet. skip over it
+            let next = self.order[number + 1..]
+                .iter()
+                .find(|&&next| self.nodes[next].weight <= weight);
+            match next {
+                Some(&next) if self.nodes[next].weight == weight => {
+                    self.leaders.insert(weight, self.nodes[next].number);
+                }
+                _ => {
+                    self.leaders.remove(&weight);
+                }
+            }
+        }
+
+        self.nodes[node].weight += 1;
+        self.leaders
+            .entry(weight + 1)
+            .and_modify(|leader| *leader = (*leader).min(number))
+            .or_insert(number);
+    }
+
+    /// Swaps two nodes, along with everything under them, between their parents.
+    fn swap(&mut self, a: usize, b: usize) {
+        let (a_parent, b_parent) = (self.nodes[a].parent.unwrap(), self.nodes[b].parent.unwrap());
+
+        if a_parent == b_parent {
+            let parent = &mut self.nodes[a_parent];
+            std::mem::swap(&mut parent.left, &mut parent.right);
+        } else {
+            self.replace_child(a_parent, a, b);
+            self.replace_child(b_parent, b, a);
+            self.nodes[a].parent = Some(b_parent);
+            self.nodes[b].parent = Some(a_parent);
+        }
+
+        let (a_number, b_number) = (self.nodes[a].number, self.nodes[b].number);
+        self.order.swap(a_number, b_number);
+        self.nodes[a].number = b_number;
+        self.nodes[b].number = a_number;
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        let parent = &mut self.nodes[parent];
+        if parent.left == Some(old) {
+            parent.left = Some(new);
+        } else {
+            parent.right = Some(new);
+        }
+    }
+}
+
+/// Codes `input` in one pass with an [`AdaptiveTree`] that starts out empty.
+pub fn get_adaptive_encoded_data<S: Symbol>(input: &[u8]) -> Result<EncodedData, HuffError> {
+    let mut tree: AdaptiveTree<S> = AdaptiveTree::new();
+    let mut bw = BitWriter::new();
+    let mut symbol_count = 0u64;
+
+    let mut incomplete = Vec::new();
+    S::split(&mut incomplete, input, |symbol| {
+        tree.encode(symbol, &mut bw);
+        symbol_count += 1;
+        Ok(())
+    })?;
+    if !incomplete.is_empty() {
+        return Err(HuffError::InvalidUtf8);
+    }
+
+    Ok(EncodedData {
+        padding_bits: (8 - bw.get_current_pos()) % 8,
+        data: bw.get_vec()?,
+        symbol_count,
+        byte_count: input.len() as u64,
+    })
+}
+
+/// Decodes `symbol_count` symbols written by `get_adaptive_encoded_data`.
+pub fn decode_adaptive_data<S: Symbol>(
+    data: &[u8],
+    symbol_count: u64,
+) -> Result<Vec<S>, HuffError> {
+    // every code is at least a bit long
+    if symbol_count > data.len() as u64 * 8 {
+        return Err(HuffError::TruncatedData);
+    }
+
+    let mut tree: AdaptiveTree<S> = AdaptiveTree::new();
+    let mut br = BitReader::new(data.to_vec());
+    let mut symbols = Vec::with_capacity(symbol_count as usize);
+    for _ in 0..symbol_count {
+        symbols.push(tree.decode(&mut br)?);
+    }
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::test_cases;
+
+    fn round_trip<S: Symbol>(input: &[u8]) -> Vec<u8> {
+        let encoded = get_adaptive_encoded_data::<S>(input).unwrap();
+        let symbols = decode_adaptive_data::<S>(&encoded.data, encoded.symbol_count).unwrap();
+
+        let mut output = Vec::new();
+        for symbol in symbols {
+            symbol.write_to(&mut output);
+        }
+        output
+    }
+
+    /// Checks that weights never go up along `order`, every internal node
+    /// weighs as much as its children and every weight's leader is the first
+    /// node with it.
+    fn check_sibling_property<S: Symbol>(tree: &AdaptiveTree<S>) {
+        for pair in tree.order.windows(2) {
+            assert!(tree.nodes[pair[0]].weight >= tree.nodes[pair[1]].weight);
+        }
+        let mut leaders = HashMap::new();
+        for (number, &node) in tree.order.iter().enumerate() {
+            leaders.entry(tree.nodes[node].weight).or_insert(number);
+        }
+        assert_eq!(tree.leaders, leaders);
+        for node in &tree.nodes {
+            if let (Some(left), Some(right)) = (node.left, node.right) {
+                assert_eq!(
+                    node.weight,
+                    tree.nodes[left].weight + tree.nodes[right].weight
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaptive_round_trip() {
+        for input in [
+            test_cases::SAMPLE_TEST,
+            test_cases::CAPITAL_TEST,
+            "a",
+            "aaaaaaaa",
+            "привет, мир! Les Misérables",
+        ] {
+            assert_eq!(round_trip::<u8>(input.as_bytes()), input.as_bytes());
+            assert_eq!(round_trip::<char>(input.as_bytes()), input.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_adaptive_all_bytes() {
+        let input: Vec<u8> = (0..=255u8).chain((0..=255u8).rev()).collect();
+
+        assert_eq!(round_trip::<u8>(&input), input);
+    }
+
+    #[test]
+    fn test_adaptive_first_symbols() {
+        // nothing to go on for the first 'a', so it's sent raw. the second one
+        // is the right leaf of the root, and costs a single bit
+        let encoded = get_adaptive_encoded_data::<u8>(b"aa").unwrap();
+
+        assert_eq!(encoded.data, vec![b'a', 0b10000000]);
+        assert_eq!(encoded.symbol_count, 2);
+    }
+
+    #[test]
+    fn test_adaptive_keeps_sibling_property() {
+        let mut tree: AdaptiveTree<u8> = AdaptiveTree::new();
+        let mut bw = BitWriter::new();
+
+        for &byte in test_cases::SAMPLE_TEST.as_bytes() {
+            tree.encode(byte, &mut bw);
+            check_sibling_property(&tree);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_many_symbols() {
+        // tens of thousands of leaves, each seen a few times, which used to
+        // take time quadratic in the number of them
+        let chars: Vec<char> = (0x4e00..0x4e00 + 30_000)
+            .filter_map(char::from_u32)
+            .collect();
+        let input: String = [chars.as_slice(); 2].concat().into_iter().collect();
+
+        assert_eq!(round_trip::<char>(input.as_bytes()), input.as_bytes());
+    }
+
+    #[test]
+    fn test_adaptive_skewed_input_compresses() {
+        let input = "a".repeat(1000) + "b";
+
+        let encoded = get_adaptive_encoded_data::<u8>(input.as_bytes()).unwrap();
+
+        assert!(encoded.data.len() < 200);
+    }
+
+    #[test]
+    fn test_adaptive_invalid_utf8() {
+        let result = get_adaptive_encoded_data::<char>(&[b'a', 0xff]);
+
+        assert!(matches!(result, Err(HuffError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn test_adaptive_truncated() {
+        let encoded = get_adaptive_encoded_data::<u8>(b"abcabc").unwrap();
+
+        let result = decode_adaptive_data::<u8>(&encoded.data[..2], encoded.symbol_count);
+
+        assert!(matches!(result, Err(HuffError::TruncatedData)));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::HuffError;
+
 pub struct BitReader {
     data: Vec<u8>,
     current_byte: usize,
@@ -14,12 +16,16 @@ impl BitReader {
     }
 
     pub fn get_current_byte(&self) -> usize {
-        return self.current_byte;
+        self.current_byte
+    }
+
+    pub fn remaining_bits(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.bits_read())
     }
 
-    pub fn read_bits(&mut self, num_bits: u32) -> Vec<u8> {
-        if (self.data.len() * 8) < num_bits as usize {
-            panic!("You tried to read more b its than exist in this BitReader")
+    pub fn read_bits(&mut self, num_bits: u32) -> Result<Vec<u8>, HuffError> {
+        if self.remaining_bits() < num_bits as usize {
+            return Err(HuffError::TruncatedData);
         }
 
         let mut bits = Vec::new();
@@ -45,7 +51,66 @@ impl BitReader {
             current_byte <<= 8 - bits_collected;
             bits.push(current_byte);
         }
-        bits
+        Ok(bits)
+    }
+
+    /// Reads `num_bits` (at most 32) bits as a single value, most significant bit first.
+    pub fn read_value(&mut self, num_bits: u8) -> Result<u32, HuffError> {
+        if self.remaining_bits() < num_bits as usize {
+            return Err(HuffError::TruncatedData);
+        }
+
+        let mut value = 0u32;
+        for _ in 0..num_bits {
+            let bit = self.next().ok_or(HuffError::TruncatedData)?;
+            value = (value << 1) | bit as u32;
+        }
+        Ok(value)
+    }
+
+    /// Total number of bits read so far.
+    pub fn bits_read(&self) -> usize {
+        self.current_byte * 8 + self.bit_position as usize
+    }
+
+    /// Looks at the next `num_bits` (at most 32) bits without consuming them.
+    /// Bits past the end of the data read as 0.
+    pub fn peek_value(&self, num_bits: u8) -> u32 {
+        if num_bits == 0 {
+            return 0;
+        }
+
+        // load 8 bytes starting at the current one so that, even after skipping
+        // the bits we've already read from it, there are at least 32 left
+        let mut window = 0u64;
+        for i in 0..8 {
+            let byte = self.data.get(self.current_byte + i).copied().unwrap_or(0);
+            window = (window << 8) | byte as u64;
+        }
+
+        ((window << self.bit_position) >> (64 - num_bits)) as u32
+    }
+
+    /// Skips over `num_bits` bits, as if they'd been read.
+    pub fn consume(&mut self, num_bits: u8) {
+        let position = self.bits_read() + num_bits as usize;
+        self.current_byte = position / 8;
+        self.bit_position = (position % 8) as u8;
+    }
+
+    /// Reads a value written by `BitWriter::write_gamma`.
+    pub fn read_gamma(&mut self) -> Result<u32, HuffError> {
+        let mut zeros = 0u8;
+        while self.next().ok_or(HuffError::TruncatedData)? == 0 {
+            zeros += 1;
+            // anything past 31 zeros wouldn't fit in a u32
+            if zeros > 31 {
+                return Err(HuffError::InvalidScalarValue(u32::MAX));
+            }
+        }
+
+        let rest = self.read_value(zeros)?;
+        Ok((1 << zeros) | rest)
     }
 }
 
@@ -67,7 +132,7 @@ impl Iterator for BitReader {
             self.bit_position += 1;
         }
 
-        return Some(bit);
+        Some(bit)
     }
 }
 
@@ -100,8 +165,56 @@ mod tests {
         let input = vec![0b10101010, 0b10001000];
         let mut br = BitReader::new(input.clone());
 
-        let char_bits = br.read_bits(16);
+        let char_bits = br.read_bits(16).unwrap();
 
         assert_eq!(input, char_bits);
     }
+
+    #[test]
+    fn test_read_value() {
+        let input = vec![0b10110000, 0b00000001];
+        let mut br = BitReader::new(input);
+
+        assert_eq!(br.read_value(4).unwrap(), 0b1011);
+        assert_eq!(br.read_value(12).unwrap(), 1);
+        assert!(matches!(br.read_value(1), Err(HuffError::TruncatedData)));
+    }
+
+    #[test]
+    fn test_read_gamma() {
+        let input = vec![0b10010100, 0b00000000, 0b00000000, 0b00000000, 0b00000000];
+        let mut br = BitReader::new(input);
+
+        assert_eq!(br.read_gamma().unwrap(), 1);
+        assert_eq!(br.read_gamma().unwrap(), 5);
+        assert!(matches!(
+            br.read_gamma(),
+            Err(HuffError::InvalidScalarValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_peek_and_consume() {
+        let input = vec![0b10110011, 0b10001111];
+        let mut br = BitReader::new(input);
+
+        assert_eq!(br.peek_value(4), 0b1011);
+        br.consume(3);
+        assert_eq!(br.bits_read(), 3);
+        assert_eq!(br.peek_value(8), 0b10011100);
+        br.consume(10);
+        // only three real bits left, the rest are padding
+        assert_eq!(br.peek_value(5), 0b11100);
+        assert_eq!(br.next(), Some(1));
+    }
+
+    #[test]
+    fn test_read_past_end() {
+        let input = vec![0b10101010];
+        let mut br = BitReader::new(input);
+
+        br.next();
+
+        assert!(matches!(br.read_bits(8), Err(HuffError::TruncatedData)));
+    }
 }
@@ -1,5 +1,8 @@
 use std::io::{self};
 
+use crate::error::HuffError;
+
+#[derive(Default)]
 pub struct BitWriter {
     buf: Vec<u8>,
     current_byte: u8,
@@ -15,11 +18,32 @@ impl BitWriter {
         }
     }
 
-    pub fn write_bit(&mut self, bit: u8) {
+    pub fn write_bit(&mut self, bit: u8) -> Result<(), HuffError> {
         if bit > 1 {
-            panic!("Invalid bit val");
+            return Err(HuffError::InvalidBit(bit));
+        }
+
+        self.push_bit(bit);
+        Ok(())
+    }
+
+    pub fn write_bits(&mut self, bits: u32, num_bits: u8) {
+        for i in (0..num_bits).rev() {
+            let bit = ((bits >> i) & 1) as u8;
+            self.push_bit(bit);
         }
+    }
+
+    /// Writes `value` (which must be at least 1) as an Elias gamma code: one
+    /// less zero than it has significant bits, then the value itself. small
+    /// values get short codes, which is what we want for gaps between sorted symbols.
+    pub fn write_gamma(&mut self, value: u32) {
+        let significant_bits = 32 - value.leading_zeros() as u8;
+        self.write_bits(0, significant_bits - 1);
+        self.write_bits(value, significant_bits);
+    }
 
+    fn push_bit(&mut self, bit: u8) {
         self.current_byte = (self.current_byte << 1) | bit;
         self.bit_position += 1;
 
@@ -30,13 +54,6 @@ impl BitWriter {
         }
     }
 
-    pub fn write_bits(&mut self, bits: u32, num_bits: u8) {
-        for i in (0..num_bits).rev() {
-            let bit = ((bits >> i) & 1) as u8;
-            self.write_bit(bit);
-        }
-    }
-
     pub fn flush(&mut self) {
         if self.bit_position > 0 {
             self.current_byte <<= 8 - self.bit_position;
@@ -64,7 +81,7 @@ mod tests {
     fn test_write_a_bit() {
         let mut bw = BitWriter::new();
 
-        bw.write_bit(1);
+        bw.write_bit(1).unwrap();
 
         assert_eq!(bw.get_vec().unwrap(), vec![0b10000000]);
     }
@@ -78,14 +95,31 @@ mod tests {
         assert_eq!(bw.get_vec().unwrap(), vec![0xDE, 0xAD, 0xBE, 0xEF]);
     }
 
+    #[test]
+    fn test_write_gamma() {
+        let mut bw = BitWriter::new();
+
+        bw.write_gamma(1);
+        bw.write_gamma(5);
+
+        assert_eq!(bw.get_vec().unwrap(), vec![0b10010100]);
+    }
+
     #[test]
     fn test_flush() {
         let mut bw = BitWriter::new();
 
-        bw.write_bit(0);
+        bw.write_bit(0).unwrap();
         bw.flush();
-        bw.write_bit(1);
+        bw.write_bit(1).unwrap();
 
         assert_eq!(bw.get_vec().unwrap(), vec![0, 128]);
     }
+
+    #[test]
+    fn test_write_invalid_bit() {
+        let mut bw = BitWriter::new();
+
+        assert!(matches!(bw.write_bit(2), Err(HuffError::InvalidBit(2))));
+    }
 }
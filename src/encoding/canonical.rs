@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use super::bitwriter::BitWriter;
+use super::symbol::Symbol;
+use crate::error::HuffError;
+
+/// Code lengths are 1 to 32 bits, so they're stored as length - 1 in 5 bits.
+pub const CODE_LENGTH_BITS: u8 = 5;
+
+/// Pulls the code length of each symbol out of a prefix table.
+pub fn get_code_lengths<S: Symbol>(prefix_table: &HashMap<S, (u32, u8)>) -> HashMap<S, u8> {
+    prefix_table
+        .iter()
+        .map(|(&symbol, &(_, length))| (symbol, length))
+        .collect()
+}
+
+/// Assigns canonical codes from code lengths alone.
+/// symbols are sorted by (length, symbol), the first one gets all zeros and every
+/// one after that gets the previous code plus one, shifted left when the length grows.
+/// since the codes only depend on the lengths, that's all the header needs to store.
+pub fn generate_canonical_prefix_table<S: Symbol>(
+    code_lengths: &HashMap<S, u8>,
+) -> Result<HashMap<S, (u32, u8)>, HuffError> {
+    let mut sorted: Vec<(u8, S)> = code_lengths
+        .iter()
+        .map(|(&symbol, &length)| (length, symbol))
+        .collect();
+    sorted.sort();
+
+    let mut prefix_table = HashMap::new();
+    let mut code: u64 = 0;
+    let mut prev_length = 0u8;
+
+    for (length, symbol) in sorted {
+        if length == 0 || length > 32 {
+            return Err(HuffError::InvalidCodeLengths);
+        }
+
+        code <<= length - prev_length;
+        // the lengths ask for more codes than fit, so they can't come from a real tree
+        if code >= 1u64 << length {
+            return Err(HuffError::InvalidCodeLengths);
+        }
+
+        prefix_table.insert(symbol, (code as u32, length));
+        code += 1;
+        prev_length = length;
+    }
+
+    Ok(prefix_table)
+}
+
+/// Writes the symbol count, then each symbol with its code length, sorted by symbol.
+/// symbols are stored as the gap from the previous one (the first one as its value
+/// plus one) in Elias gamma codes, so runs of nearby symbols like the letters in a
+/// text file cost a bit or two each. lengths take [`CODE_LENGTH_BITS`] bits.
+pub fn generate_canonical_header<S: Symbol>(code_lengths: &HashMap<S, u8>, bw: &mut BitWriter) {
+    let mut sorted: Vec<(S, u8)> = code_lengths
+        .iter()
+        .map(|(&symbol, &length)| (symbol, length))
+        .collect();
+    sorted.sort();
+
+    // plus one since a gamma code can't hold 0
+    bw.write_gamma(sorted.len() as u32 + 1);
+    let mut next = 0u32;
+    for (symbol, length) in sorted {
+        bw.write_gamma(symbol.to_bits() - next + 1);
+        bw.write_bits(length as u32 - 1, CODE_LENGTH_BITS);
+        next = symbol.to_bits() + 1;
+    }
+}
+
+pub fn get_canonical_header_with_size<S: Symbol>(
+    code_lengths: &HashMap<S, u8>,
+) -> Result<(u32, Vec<u8>), HuffError> {
+    let mut bw = BitWriter::new();
+    generate_canonical_header(code_lengths, &mut bw);
+    let header = bw.get_vec()?;
+    let header_size = header.len() as u32;
+
+    Ok((header_size, header))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::encoding::generate_prefix_table;
+    use crate::encoding::frequency::Freq;
+    use crate::encoding::test_cases;
+    use crate::encoding::tree::generate_tree;
+
+    use super::*;
+
+    #[test]
+    fn test_canonical_codes() {
+        let code_lengths = HashMap::from([('a', 2), ('b', 1), ('c', 3), ('d', 3)]);
+
+        let prefix_table = generate_canonical_prefix_table(&code_lengths).unwrap();
+
+        assert_eq!(prefix_table.get(&'b').unwrap(), &(0b0, 1));
+        assert_eq!(prefix_table.get(&'a').unwrap(), &(0b10, 2));
+        assert_eq!(prefix_table.get(&'c').unwrap(), &(0b110, 3));
+        assert_eq!(prefix_table.get(&'d').unwrap(), &(0b111, 3));
+    }
+
+    #[test]
+    fn test_canonical_single_symbol() {
+        let code_lengths = HashMap::from([(b'a', 1)]);
+
+        let prefix_table = generate_canonical_prefix_table(&code_lengths).unwrap();
+
+        assert_eq!(prefix_table.get(&b'a').unwrap(), &(0, 1));
+    }
+
+    #[test]
+    fn test_canonical_oversubscribed() {
+        let code_lengths = HashMap::from([('a', 1), ('b', 1), ('c', 1)]);
+
+        let result = generate_canonical_prefix_table(&code_lengths);
+
+        assert!(matches!(result, Err(HuffError::InvalidCodeLengths)));
+    }
+
+    #[test]
+    fn test_canonical_keeps_lengths() {
+        let mut freq: Freq = Freq::new();
+        freq.update(test_cases::SAMPLE_TEST.as_bytes()).unwrap();
+        let prefix_table = generate_prefix_table(generate_tree(&freq)).unwrap();
+        let code_lengths = get_code_lengths(&prefix_table);
+
+        let canonical = generate_canonical_prefix_table(&code_lengths).unwrap();
+
+        assert_eq!(get_code_lengths(&canonical), code_lengths);
+    }
+
+    #[test]
+    fn test_canonical_header() {
+        let code_lengths = HashMap::from([(b'b', 1), (b'a', 1)]);
+        let expected = vec![
+            // 3 for two symbols, 98 for 'a', length 1, 1 for 'b', length 1
+            0b01100000, 0b01100010, 0b00000100, 0b00000000,
+        ];
+
+        let (header_size, header) = get_canonical_header_with_size(&code_lengths).unwrap();
+
+        assert_eq!(header_size, 4);
+        assert_eq!(header, expected);
+    }
+}
@@ -0,0 +1,89 @@
+/// CRC-32 as used by zip, gzip and png (reflected, polynomial 0xEDB88320).
+/// Data can be fed in over several `update` calls, so the checksum is built up
+/// while the input streams past instead of needing its own pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32 {
+    state: u32,
+}
+
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 { state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            let index = (self.state ^ byte as u32) & 0xff;
+            self.state = (self.state >> 8) ^ TABLE[index as usize];
+        }
+    }
+
+    /// The checksum of everything passed to `update` so far.
+    pub fn value(&self) -> u32 {
+        !self.state
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_crc32_empty() {
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_crc32_in_pieces() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+        let mut crc = Crc32::new();
+
+        for chunk in data.chunks(5) {
+            crc.update(chunk);
+        }
+
+        assert_eq!(crc.value(), 0x414F_A339);
+        assert_eq!(crc.value(), crc32(data));
+    }
+}
@@ -1,7 +1,11 @@
 use std::collections::HashMap;
 
 use super::bitreader::BitReader;
-use super::tree::HuffNode;
+use super::canonical::{generate_canonical_prefix_table, CODE_LENGTH_BITS};
+use super::symbol::Symbol;
+use super::table_decoder::decode_data_with_table;
+use super::tree::{HuffNode, HuffmanTree};
+use crate::error::HuffError;
 
 /// how to start decoding...
 /// we should first read the header from the file and rebuild the huffman tree to decode
@@ -9,119 +13,133 @@ use super::tree::HuffNode;
 /// we should go from <string of bits> -> prefix table
 ///
 /// next we just decode the file and restore the data to its original state
-///
-
-pub fn decode_tree_header_with_size_impl(
-    tree_data: &Vec<u8>,
+pub fn decode_tree_header_with_size_impl<S: Symbol>(
     br: &mut BitReader,
-) -> Option<Box<HuffNode>> {
-    let curr_bit = br.next().unwrap();
-
-    if curr_bit == 1u8 {
-        // it's a leaf node
-        let char_bits = br.read_bits(32);
-
-        let u32_check: [u8; 4] = char_bits
-            .try_into()
-            .expect("Vec<u8> must have exactly 4 elements");
-        let char_as_u32 = u32::from_be_bytes(u32_check);
-
-        let decode_char = match char::from_u32(char_as_u32) {
-            Some(c) => c,
-            None => panic!(
-                "from_u32 error: {} is not a valid Unicode scalar value.",
-                char_as_u32
-            ),
-        };
-
-        let ret_node = HuffNode::new(Some(decode_char), 0);
-        Some(Box::new(ret_node))
-    } else {
-        let left: Option<Box<HuffNode>> = decode_tree_header_with_size_impl(&tree_data, br);
-        let right = decode_tree_header_with_size_impl(&tree_data, br);
-
-        let mut ret_node = HuffNode::new(None, 0);
-
-        ret_node.left = left;
-        ret_node.right = right;
-
-        Some(Box::new(ret_node))
-    }
+) -> Result<Option<Box<HuffNode<S>>>, HuffError> {
+    Ok(HuffmanTree::read_header(br)?.to_boxed())
+}
+
+pub fn decode_tree_header_with_size<S: Symbol>(
+    tree_data: &[u8],
+) -> Result<Option<Box<HuffNode<S>>>, HuffError> {
+    Ok(HuffmanTree::from_header(tree_data)?.to_boxed())
 }
 
-pub fn decode_tree_header_with_size(tree_data: &Vec<u8>) -> Option<Box<HuffNode>> {
-    let mut br = BitReader::new(tree_data.to_vec());
-    decode_tree_header_with_size_impl(tree_data, &mut br)
+/// Reads a header written by `generate_canonical_header` and rebuilds the
+/// prefix table straight from the code lengths, no tree needed.
+pub fn decode_canonical_header_with_size<S: Symbol>(
+    header_data: &[u8],
+) -> Result<HashMap<S, (u32, u8)>, HuffError> {
+    let mut br = BitReader::new(header_data.to_vec());
+    let symbol_count = br.read_gamma().map_err(|_| HuffError::TruncatedHeader)? - 1;
+
+    // every symbol takes up at least this many bits, so a huge count in a
+    // short header is caught here rather than after allocating for it
+    let min_entry_bits = 1 + CODE_LENGTH_BITS as usize;
+    if br.remaining_bits() / min_entry_bits < symbol_count as usize {
+        return Err(HuffError::TruncatedHeader);
+    }
+
+    let mut code_lengths = HashMap::with_capacity(symbol_count as usize);
+    let mut next = 0u32;
+    for _ in 0..symbol_count {
+        let gap = br.read_gamma().map_err(|_| HuffError::TruncatedHeader)?;
+        let bits = next
+            .checked_add(gap - 1)
+            .ok_or(HuffError::InvalidScalarValue(u32::MAX))?;
+        let symbol = S::from_bits(bits)?;
+        let length = br
+            .read_value(CODE_LENGTH_BITS)
+            .map_err(|_| HuffError::TruncatedHeader)? as u8
+            + 1;
+
+        code_lengths.insert(symbol, length);
+        next = bits + 1;
+    }
+
+    generate_canonical_prefix_table(&code_lengths)
 }
 
-pub fn invert_prefix_table(prefix_table: HashMap<char, (u32, u8)>) -> HashMap<(u32, u8), char> {
-    let mut inverted_prefix_table: HashMap<(u32, u8), char> = HashMap::new();
+pub fn invert_prefix_table<S: Symbol>(
+    prefix_table: HashMap<S, (u32, u8)>,
+) -> Result<HashMap<(u32, u8), S>, HuffError> {
+    let mut inverted_prefix_table: HashMap<(u32, u8), S> = HashMap::new();
 
     for (c, (prefix, prefix_length)) in prefix_table {
         if inverted_prefix_table.contains_key(&(prefix, prefix_length)) {
-            panic!(
-                "Error in invert_prefix_table(), key already exists: {:?}",
-                &(prefix, prefix_length)
-            )
+            return Err(HuffError::DuplicatePrefix {
+                prefix,
+                length: prefix_length,
+            });
         }
         inverted_prefix_table.insert((prefix, prefix_length), c);
     }
 
-    inverted_prefix_table
+    Ok(inverted_prefix_table)
 }
 
-pub fn decode_data(data: &Vec<u8>, prefix_table: HashMap<char, (u32, u8)>) -> Vec<char> {
-    // number of bits to read from the encoded data's last byte
-    let last_byte = *data.last().unwrap();
-    let bytes_to_read_in_last_byte = match last_byte {
-        0 => 8u8,
-        _ => last_byte,
-    };
+/// Which decoder `decode_data_with_strategy` uses. Both give the same output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeStrategy {
+    /// Read one bit at a time and look up the prefix so far after each one, see `decode_data`.
+    BitByBit,
+    /// Look up whole codes in a `DecodeTable`, see `decode_data_with_table`.
+    #[default]
+    Table,
+}
 
-    let data_length = data.len() - 2;
+pub fn decode_data_with_strategy<S: Symbol>(
+    data: &[u8],
+    prefix_table: HashMap<S, (u32, u8)>,
+    symbol_count: u64,
+    strategy: DecodeStrategy,
+) -> Result<Vec<S>, HuffError> {
+    match strategy {
+        DecodeStrategy::BitByBit => decode_data(data, prefix_table, symbol_count),
+        DecodeStrategy::Table => decode_data_with_table(data, prefix_table, symbol_count),
+    }
+}
+
+/// Decodes exactly `symbol_count` symbols from `data`. Whatever is left of the
+/// last byte after that is padding.
+pub fn decode_data<S: Symbol>(
+    data: &[u8],
+    prefix_table: HashMap<S, (u32, u8)>,
+    symbol_count: u64,
+) -> Result<Vec<S>, HuffError> {
+    // every code is at least a bit long, which also keeps a corrupt count
+    // from making us allocate more than the data could ever hold
+    if symbol_count > data.len() as u64 * 8 {
+        return Err(HuffError::TruncatedData);
+    }
 
-    let mut characters: Vec<char> = Vec::new();
+    let mut characters: Vec<S> = Vec::with_capacity(symbol_count as usize);
 
     let mut br = BitReader::new(data.to_vec()); // yes i know this copies, i am lazy
-    let inverted_prefix_table = invert_prefix_table(prefix_table);
+    let inverted_prefix_table = invert_prefix_table(prefix_table)?;
     let mut curr_prefix: u32 = 0;
     let mut curr_prefix_length = 0u8;
 
-    while br.get_current_byte() < data_length {
-        curr_prefix = (curr_prefix << 1) | ((br.next().unwrap()) as u32 & 1);
-        curr_prefix_length += 1;
-        if inverted_prefix_table.contains_key(&(curr_prefix, curr_prefix_length)) {
-            characters.push(
-                inverted_prefix_table
-                    .get(&(curr_prefix, curr_prefix_length))
-                    .unwrap()
-                    .clone(),
-            );
-            curr_prefix = 0;
-            curr_prefix_length = 0u8;
-        }
-    }
-
-    for _ in 0..bytes_to_read_in_last_byte {
-        curr_prefix = (curr_prefix << 1) | ((br.next().unwrap() as u32) & 1);
+    while (characters.len() as u64) < symbol_count {
+        let bit = br.next().ok_or(HuffError::TruncatedData)?;
+        curr_prefix = (curr_prefix << 1) | (bit as u32 & 1);
         curr_prefix_length += 1;
-        if inverted_prefix_table.contains_key(&(curr_prefix, curr_prefix_length)) {
-            characters.push(
-                inverted_prefix_table
-                    .get(&(curr_prefix, curr_prefix_length))
-                    .unwrap()
-                    .clone(),
-            );
+        if let Some(&character) = inverted_prefix_table.get(&(curr_prefix, curr_prefix_length)) {
+            characters.push(character);
             curr_prefix = 0;
             curr_prefix_length = 0u8;
+        } else if curr_prefix_length == 32 {
+            // no code is longer than 32 bits, so this can never match
+            return Err(HuffError::UnknownPrefix);
         }
     }
 
-    characters
+    Ok(characters)
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::encoding::canonical::{get_canonical_header_with_size, get_code_lengths};
     use crate::encoding::frequency::Freq;
     use crate::encoding::tree::{self, generate_tree};
     use crate::encoding::{
@@ -142,13 +160,13 @@ mod tests {
             0b10000000, // This is the encoded tree
         ];
         let expected = "aaa";
-        let mut freq = Freq::new();
-        freq.update(expected.as_bytes());
+        let mut freq: Freq = Freq::new();
+        freq.update(expected.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let expected_prefix = generate_prefix_table(root);
+        let expected_prefix = generate_prefix_table(root).unwrap();
 
-        let result_tree = decode_tree_header_with_size(&input);
-        let result_prefix = generate_prefix_table(result_tree);
+        let result_tree = decode_tree_header_with_size(&input).unwrap();
+        let result_prefix = generate_prefix_table(result_tree).unwrap();
 
         assert_eq!(result_prefix, expected_prefix);
     }
@@ -160,144 +178,173 @@ mod tests {
             0b00001100, 0b00100000,
         ];
         let expected = "aab";
-        let mut freq = Freq::new();
-        freq.update(expected.as_bytes());
+        let mut freq: Freq = Freq::new();
+        freq.update(expected.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let expected_prefix = generate_prefix_table(root);
+        let expected_prefix = generate_prefix_table(root).unwrap();
 
-        let result_tree = decode_tree_header_with_size(&input);
-        let result_prefix = generate_prefix_table(result_tree);
+        let result_tree = decode_tree_header_with_size(&input).unwrap();
+        let result_prefix = generate_prefix_table(result_tree).unwrap();
 
         assert_eq!(result_prefix, expected_prefix);
     }
 
     #[test]
     fn test_decode_encoded_sample_header() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = test_cases::SAMPLE_TEST;
-        freq.update(test_input.as_bytes());
+        freq.update(test_input.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let (_, encoded_header) = get_tree_header_with_size(&root);
-        let expected_prefix = generate_prefix_table(root);
+        let (_, encoded_header) = get_tree_header_with_size(&root).unwrap();
+        let expected_prefix = generate_prefix_table(root).unwrap();
 
-        let decoded_header = decode_tree_header_with_size(&encoded_header);
-        let result_prefix = generate_prefix_table(decoded_header);
+        let decoded_header = decode_tree_header_with_size(&encoded_header).unwrap();
+        let result_prefix = generate_prefix_table(decoded_header).unwrap();
 
         assert_eq!(result_prefix, expected_prefix);
     }
 
     #[test]
     fn test_decode_encoded_capital_header() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = test_cases::CAPITAL_TEST;
-        freq.update(test_input.as_bytes());
+        freq.update(test_input.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let (_, encoded_header) = get_tree_header_with_size(&root);
-        let expected_prefix = generate_prefix_table(root);
+        let (_, encoded_header) = get_tree_header_with_size(&root).unwrap();
+        let expected_prefix = generate_prefix_table(root).unwrap();
+
+        let decoded_header = decode_tree_header_with_size(&encoded_header).unwrap();
+        let result_prefix = generate_prefix_table(decoded_header).unwrap();
+
+        assert_eq!(result_prefix, expected_prefix);
+    }
 
-        let decoded_header = decode_tree_header_with_size(&encoded_header);
-        let result_prefix = generate_prefix_table(decoded_header);
+    #[test]
+    fn test_decode_canonical_header() {
+        let mut freq: Freq = Freq::new();
+        freq.update(test_cases::CAPITAL_TEST.as_bytes()).unwrap();
+        let prefix_table = generate_prefix_table(generate_tree(&freq)).unwrap();
+        let code_lengths = get_code_lengths(&prefix_table);
+        let expected_prefix = generate_canonical_prefix_table(&code_lengths).unwrap();
+        let (_, encoded_header) = get_canonical_header_with_size(&code_lengths).unwrap();
+
+        let result_prefix = decode_canonical_header_with_size(&encoded_header).unwrap();
 
         assert_eq!(result_prefix, expected_prefix);
     }
 
+    #[test]
+    fn test_decode_truncated_canonical_header() {
+        // says there are 1000 symbols but has room for none of them
+        let input = vec![0b00000000, 0b01111101, 0b00100000];
+
+        let result = decode_canonical_header_with_size::<u8>(&input);
+
+        assert!(matches!(result, Err(HuffError::TruncatedHeader)));
+    }
+
     /// These tests for decoding data assume that we've consumed and read
-    /// the size of the data already, which are the first 4 bytes after the
-    /// tree header data.
+    /// the symbol count and size of the data already, which come right after
+    /// the tree header data.
     #[test]
     fn test_decode_data_one_distinct_char() {
-        let input: Vec<u8> = vec![0b00000000, 0b00000011];
+        let input: Vec<u8> = vec![0b00000000];
         let expected = "aaa";
 
-        let mut freq = Freq::new();
-        freq.update(expected.as_bytes());
+        let mut freq: Freq = Freq::new();
+        freq.update(expected.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
 
-        let result = decode_data(&input, prefix_table);
+        let result = decode_data(&input, prefix_table, expected.chars().count() as u64).unwrap();
 
         assert_eq!(expected.chars().collect::<Vec<char>>(), result);
     }
 
     #[test]
     fn test_decode_data_two_distinct_chars() {
-        let input: Vec<u8> = vec![0b11000000, 0b00000011];
+        let input: Vec<u8> = vec![0b11000000];
 
         let expected = "aab";
 
-        let mut freq = Freq::new();
-        freq.update(expected.as_bytes());
+        let mut freq: Freq = Freq::new();
+        freq.update(expected.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
 
-        let result = decode_data(&input, prefix_table);
+        let result = decode_data(&input, prefix_table, expected.chars().count() as u64).unwrap();
 
         assert_eq!(expected.chars().collect::<Vec<char>>(), result);
     }
 
     #[test]
     fn test_decode_data_three_distinct_chars() {
-        let input: Vec<u8> = vec![0b11111110, 0b00000000, 0b00000100];
+        let input: Vec<u8> = vec![0b11111110, 0b00000000];
 
         let expected = "aaabcccc";
 
-        let mut freq = Freq::new();
-        freq.update(expected.as_bytes());
+        let mut freq: Freq = Freq::new();
+        freq.update(expected.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
 
-        let result = decode_data(&input, prefix_table);
+        let result = decode_data(&input, prefix_table, expected.chars().count() as u64).unwrap();
 
         assert_eq!(expected.chars().collect::<Vec<char>>(), result);
     }
 
     #[test]
     fn test_decode_data_no_distinct_chars() {
-        let input: Vec<u8> = vec![0b00011011, 0b00000000];
+        let input: Vec<u8> = vec![0b00011011];
 
         let expected = "abcd";
 
-        let mut freq = Freq::new();
-        freq.update(expected.as_bytes());
+        let mut freq: Freq = Freq::new();
+        freq.update(expected.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
 
-        let result = decode_data(&input, prefix_table);
+        let result = decode_data(&input, prefix_table, expected.chars().count() as u64).unwrap();
 
         assert_eq!(expected.chars().collect::<Vec<char>>(), result);
     }
     #[test]
     fn test_encode_decode_small_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaabccccDJEisérables
 Com";
-        freq.update(test_input.as_bytes());
+        freq.update(test_input.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
         let test_file = Cursor::new(test_input.as_bytes());
 
-        let encoded_data = get_encoded_data(test_file, prefix_table.clone());
+        let encoded_data = get_encoded_data(test_file, prefix_table.clone()).unwrap();
 
-        let rez = decode_data(&encoded_data, prefix_table);
+        let rez = decode_data(&encoded_data.data, prefix_table, encoded_data.symbol_count).unwrap();
 
         assert_eq!(rez, test_input.chars().collect::<Vec<char>>());
     }
 
     #[test]
     fn test_encode_decode_capital_letter_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = test_cases::CAPITAL_TEST;
-        freq.update(test_input.as_bytes());
+        freq.update(test_input.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let (_, encoded_header) = get_tree_header_with_size(&root);
-        let prefix_table = generate_prefix_table(root);
+        let (_, encoded_header) = get_tree_header_with_size(&root).unwrap();
+        let prefix_table = generate_prefix_table(root).unwrap();
         let test_file = Cursor::new(test_input.as_bytes());
-        let decoded_tree = decode_tree_header_with_size(&encoded_header);
-        let decoded_prefix_table = generate_prefix_table(decoded_tree);
+        let decoded_tree = decode_tree_header_with_size(&encoded_header).unwrap();
+        let decoded_prefix_table = generate_prefix_table(decoded_tree).unwrap();
 
-        let encoded_data = get_encoded_data(test_file, prefix_table.clone());
+        let encoded_data = get_encoded_data(test_file, prefix_table.clone()).unwrap();
 
-        let rez = decode_data(&encoded_data, decoded_prefix_table.clone());
+        let rez = decode_data(
+            &encoded_data.data,
+            decoded_prefix_table.clone(),
+            encoded_data.symbol_count,
+        )
+        .unwrap();
 
         // for byte in encoded_data {
         //     println!("{:08b}", byte);
@@ -313,24 +360,53 @@ Com";
 
     #[test]
     fn test_encode_decode_sample_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = test_cases::SAMPLE_TEST;
-        freq.update(test_input.as_bytes());
+        freq.update(test_input.as_bytes()).unwrap();
         let root = generate_tree(&freq);
-        let (_, encoded_header) = get_tree_header_with_size(&root);
+        let (_, encoded_header) = get_tree_header_with_size(&root).unwrap();
         tree::print_tree(&root, "", true);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
         let test_file = Cursor::new(test_input.as_bytes());
-        let decoded_tree = decode_tree_header_with_size(&encoded_header);
+        let decoded_tree = decode_tree_header_with_size(&encoded_header).unwrap();
         println!("ddddd");
         tree::print_tree(&decoded_tree, "", true);
-        let decoded_prefix_table = generate_prefix_table(decoded_tree);
+        let decoded_prefix_table = generate_prefix_table(decoded_tree).unwrap();
 
-        let encoded_data = get_encoded_data(test_file, prefix_table.clone());
+        let encoded_data = get_encoded_data(test_file, prefix_table.clone()).unwrap();
 
-        let rez = decode_data(&encoded_data, decoded_prefix_table.clone());
+        let rez = decode_data(
+            &encoded_data.data,
+            decoded_prefix_table.clone(),
+            encoded_data.symbol_count,
+        )
+        .unwrap();
 
         assert_eq!(prefix_table.clone(), decoded_prefix_table.clone());
         assert_eq!(rez, test_input.chars().collect::<Vec<char>>());
     }
+
+    #[test]
+    fn test_decode_data_ignores_padding() {
+        // 'a' is 0 and 'b' is 10, and the zeros after the third symbol are padding
+        let prefix_table = HashMap::from([('a', (0b0, 1)), ('b', (0b10, 2))]);
+
+        let result = decode_data(&[0b10010000], prefix_table, 3).unwrap();
+
+        assert_eq!(result, vec!['b', 'a', 'b']);
+    }
+
+    #[test]
+    fn test_decode_data_runs_out() {
+        let prefix_table = HashMap::from([('a', (0b0, 1)), ('b', (0b10, 2))]);
+
+        assert!(matches!(
+            decode_data(&[0b10101010], prefix_table.clone(), 5),
+            Err(HuffError::TruncatedData)
+        ));
+        assert!(matches!(
+            decode_data(&[0], prefix_table, 9),
+            Err(HuffError::TruncatedData)
+        ));
+    }
 }
@@ -1,146 +1,167 @@
-use super::{bitwriter::BitWriter, tree::HuffNode};
+use super::bitwriter::BitWriter;
+use super::crc32::Crc32;
+use super::frequency::Freq;
+use super::symbol::Symbol;
+use super::tree::{HuffNode, HuffmanTree};
+use crate::error::HuffError;
 use std::collections::HashMap;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, ErrorKind, Read};
 
-pub fn get_prefixes(
-    node: &Option<Box<HuffNode>>,
+pub fn get_prefixes<S: Symbol>(
+    node: &Option<Box<HuffNode<S>>>,
     state: u32,
-    prefix: &mut HashMap<char, (u32, u8)>,
+    prefix: &mut HashMap<S, (u32, u8)>,
     meaningful_bits: u8,
-) {
-    if node.is_none() {
-        return;
-    }
+) -> Result<(), HuffError> {
+    let tree = HuffmanTree::from_boxed(node);
 
-    if let Some(curr_node) = node {
-        if let Some(character) = curr_node.character {
-            if prefix.contains_key(&character) {
-                panic!("Character already exists");
-            }
-            prefix.insert(character, (state, meaningful_bits));
-        } else {
-            get_prefixes(&curr_node.left, state << 1, prefix, meaningful_bits + 1);
-            get_prefixes(
-                &curr_node.right,
-                state << 1 | 1,
-                prefix,
-                meaningful_bits + 1,
-            );
-        }
+    match tree.root() {
+        Some(root) => tree.collect_prefixes(root, state, meaningful_bits, prefix),
+        None => Ok(()),
     }
 }
 
 /// Generates a prefix table from a Huffman tree
-/// it takes char as a key. the tuple values are (prefix, number of meaningful bits)
+/// it takes the symbol (a char or a byte) as a key. the tuple values are (prefix, number of meaningful bits)
 /// the meaningful bits piece is used so we know how many to write while encoding
 /// so we can pack bits tight
-pub fn generate_prefix_table(root: Option<Box<HuffNode>>) -> HashMap<char, (u32, u8)> {
-    let mut prefix_table = HashMap::new();
-    let state: u32 = 0;
-
-    // special case: there is one distinct char in the input, so there is only one meaningful bit.
-    // as-is, it would get assigned a 0 for meaningful_bits, so we handle that here
-    if let Some(node) = &root {
-        if let Some(_character) = node.character {
-            get_prefixes(&root, state, &mut prefix_table, 1);
-        } else {
-            get_prefixes(&root, state, &mut prefix_table, 0);
-        }
-    }
+pub fn generate_prefix_table<S: Symbol>(
+    root: Option<Box<HuffNode<S>>>,
+) -> Result<HashMap<S, (u32, u8)>, HuffError> {
+    HuffmanTree::from_boxed(&root).prefix_table()
+}
 
-    prefix_table
+/// One row of `code_table`: a symbol, how often it came up, and its code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeEntry<S = char> {
+    pub symbol: S,
+    pub count: u64,
+    pub code: u32,
+    pub length: u8,
 }
 
-pub fn generate_header(node: &Option<Box<HuffNode>>, bw: &mut BitWriter) {
-    if node.is_none() {
-        return;
+impl<S> CodeEntry<S> {
+    /// The code as a string of 0s and 1s.
+    pub fn bits(&self) -> String {
+        format!("{:0width$b}", self.code, width = self.length as usize)
     }
+}
 
-    let curr_node = node.as_ref().unwrap();
-
-    match curr_node.character {
-        Some(character) => {
-            let bits = character as u32;
-            bw.write_bit(1);
-            bw.write_bits(bits, 32);
-        }
-
-        None => {
-            bw.write_bit(0);
-        }
-    }
+/// Lists every symbol in `prefix_table` with its count from `freq`, most
+/// frequent first. Symbols with the same count are in symbol order.
+pub fn code_table<S: Symbol>(
+    freq: &Freq<S>,
+    prefix_table: &HashMap<S, (u32, u8)>,
+) -> Vec<CodeEntry<S>> {
+    let mut entries: Vec<CodeEntry<S>> = prefix_table
+        .iter()
+        .map(|(&symbol, &(code, length))| CodeEntry {
+            symbol,
+            count: freq.counter.get(&symbol).copied().unwrap_or(0),
+            code,
+            length,
+        })
+        .collect();
+    entries.sort_by(|a, b| b.count.cmp(&a.count).then(a.symbol.cmp(&b.symbol)));
+    entries
+}
 
-    generate_header(&curr_node.left, bw);
-    generate_header(&curr_node.right, bw);
+/// Writes the tree in preorder: a 0 bit for an internal node, and a 1 bit
+/// followed by the symbol in `S::BITS` bits for a leaf.
+pub fn generate_header<S: Symbol>(
+    node: &Option<Box<HuffNode<S>>>,
+    bw: &mut BitWriter,
+) -> Result<(), HuffError> {
+    HuffmanTree::from_boxed(node).write_header(bw)
 }
 
-pub fn get_tree_header_with_size(node: &Option<Box<HuffNode>>) -> (u32, Vec<u8>) {
-    let mut bw = BitWriter::new();
-    generate_header(&node, &mut bw);
-    let header = bw.get_vec().unwrap();
-    let header_size = header.len() as u32;
+pub fn get_tree_header_with_size<S: Symbol>(
+    node: &Option<Box<HuffNode<S>>>,
+) -> Result<(u32, Vec<u8>), HuffError> {
+    HuffmanTree::from_boxed(node).header_with_size()
+}
 
-    (header_size, header)
+/// What `get_encoded_data` produces: the packed codes, plus how many symbols
+/// and input bytes went into them so the decoder knows exactly where to stop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedData {
+    pub data: Vec<u8>,
+    pub symbol_count: u64,
+    pub byte_count: u64,
+    /// How many zero bits fill out the last byte of `data`.
+    pub padding_bits: u8,
 }
 
-fn get_encoded_data_impl(
-    prefix_table: &HashMap<char, (u32, u8)>,
+fn get_encoded_data_impl<S: Symbol>(
+    prefix_table: &HashMap<S, (u32, u8)>,
     bw: &mut BitWriter,
     incomplete: &mut Vec<u8>,
+    symbol_count: &mut u64,
     chunk: &[u8],
-) {
-    let mut data = incomplete.clone();
-    data.extend_from_slice(&chunk);
-
-    let (valid, curr_incomplete) = match std::str::from_utf8(&data) {
-        Ok(valid_str) => (valid_str, &[] as &[u8]),
-        Err(e) => {
-            let valid_up_to = e.valid_up_to();
-            (
-                std::str::from_utf8(&data[..valid_up_to]).unwrap(),
-                &data[valid_up_to..],
-            )
-        }
-    };
-
-    for ch in valid.chars() {
-        let &(curr_prefix, meaningful_bits) = prefix_table.get(&ch).unwrap();
-        bw.write_bits(curr_prefix as u32, meaningful_bits);
-    }
+) -> Result<(), HuffError> {
+    S::split(incomplete, chunk, |symbol| {
+        let &(curr_prefix, meaningful_bits) = prefix_table
+            .get(&symbol)
+            .ok_or(HuffError::UnknownSymbol(symbol.to_bits()))?;
+        bw.write_bits(curr_prefix, meaningful_bits);
+        *symbol_count += 1;
+        Ok(())
+    })
+}
 
-    incomplete.clear();
-    incomplete.extend_from_slice(curr_incomplete);
+/// Encodes everything in `file` with `prefix_table`. The last byte is padded
+/// with zeros, which the decoder skips since it knows the symbol count.
+pub fn get_encoded_data<S: Symbol, R: Read>(
+    file: R,
+    prefix_table: HashMap<S, (u32, u8)>,
+) -> Result<EncodedData, HuffError> {
+    get_encoded_data_with_checksum(file, prefix_table, &mut Crc32::new())
 }
 
-pub fn get_encoded_data<R: Read>(file: R, prefix_table: HashMap<char, (u32, u8)>) -> Vec<u8> {
+/// Same as `get_encoded_data`, but also runs every byte read through `checksum`.
+pub fn get_encoded_data_with_checksum<S: Symbol, R: Read>(
+    file: R,
+    prefix_table: HashMap<S, (u32, u8)>,
+    checksum: &mut Crc32,
+) -> Result<EncodedData, HuffError> {
     let mut bw = BitWriter::new();
     let mut incomplete: Vec<u8> = Vec::new();
+    let mut symbol_count = 0u64;
+    let mut byte_count = 0u64;
 
     let mut reader = BufReader::new(file);
     let mut buffer = [0; 1024];
 
-    while let Ok(bytes_read) = reader.read(&mut buffer) {
-        if bytes_read == 0 {
-            break;
-        }
+    loop {
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => bytes_read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
 
+        checksum.update(&buffer[..bytes_read]);
+        byte_count += bytes_read as u64;
         get_encoded_data_impl(
             &prefix_table,
             &mut bw,
             &mut incomplete,
+            &mut symbol_count,
             &buffer[..bytes_read],
-        );
+        )?;
     }
 
-    let current_bit_pos = bw.get_current_pos() as u32;
-    bw.flush();
-    // Here, we are adding the bit position of the last bit we should read
-    // in the last byte of encoded data. we append this to the encoded data
-    // so we can read it first during decoding, so we know when to stop
-    // while reading bits from the last byte.
-    bw.write_bits(current_bit_pos, 8);
+    // whatever is left over never became a whole symbol
+    if !incomplete.is_empty() {
+        return Err(HuffError::InvalidUtf8);
+    }
 
-    bw.get_vec().unwrap()
+    Ok(EncodedData {
+        padding_bits: (8 - bw.get_current_pos()) % 8,
+        data: bw.get_vec()?,
+        symbol_count,
+        byte_count,
+    })
 }
 
 #[cfg(test)]
@@ -151,26 +172,59 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_code_table() {
+        let mut freq: Freq = Freq::new();
+        freq.update("aaabcccc".as_bytes()).unwrap();
+        let prefix_table = generate_prefix_table(generate_tree(&freq)).unwrap();
+
+        let entries = code_table(&freq, &prefix_table);
+
+        let rows: Vec<(char, u64, String)> = entries
+            .iter()
+            .map(|entry| (entry.symbol, entry.count, entry.bits()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ('c', 4, "0".to_string()),
+                ('a', 3, "11".to_string()),
+                ('b', 1, "10".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_table_symbol_not_counted() {
+        let freq: Freq<u8> = Freq::new();
+        let prefix_table = HashMap::from([(b'a', (0b01, 2))]);
+
+        let entries = code_table(&freq, &prefix_table);
+
+        assert_eq!(entries[0].count, 0);
+        assert_eq!(entries[0].bits(), "01");
+    }
+
     #[test]
     fn test_single_letter_prefix_table() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaa".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
 
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
 
         assert_eq!(prefix_table.get(&'a').unwrap(), &(0, 1));
     }
 
     #[test]
     fn test_two_letter_prefix_table() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaab".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
 
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
 
         assert_eq!(prefix_table.get(&'a').unwrap(), &(1, 1));
         assert_eq!(prefix_table.get(&'b').unwrap(), &(0, 1));
@@ -178,12 +232,12 @@ mod tests {
 
     #[test]
     fn test_three_letter_prefix_table() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaabcccc".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
 
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
         /*
         for (key, value) in prefix_table.into_iter() {
             println!("{} -> {}", key, value);
@@ -197,18 +251,59 @@ mod tests {
     // this fails, result prefix table value is indeterminate!
     #[test]
     fn test_four_unique_letter_prefix_table() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "abcd".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
 
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
 
         assert_eq!(prefix_table.get(&'a').unwrap(), &(0, 2));
         assert_eq!(prefix_table.get(&'b').unwrap(), &(1, 2));
         assert_eq!(prefix_table.get(&'c').unwrap(), &(2, 2));
         assert_eq!(prefix_table.get(&'d').unwrap(), &(3, 2));
     }
+    #[test]
+    fn test_prefix_table_too_deep() {
+        // a degenerate tree where every internal node has a leaf on the left,
+        // so the deepest leaves need 33 bits
+        let mut root = Box::new(HuffNode::new(Some('z'), 1));
+        for i in 0..33 {
+            let mut parent = Box::new(HuffNode::new(None, 0));
+            parent.left = Some(Box::new(HuffNode::new(char::from_u32('a' as u32 + i), 1)));
+            parent.right = Some(root);
+            root = parent;
+        }
+
+        let result = generate_prefix_table(Some(root));
+
+        assert!(matches!(result, Err(HuffError::CodeTooLong(33))));
+    }
+
+    #[test]
+    fn test_prefix_table_duplicate_symbol() {
+        let mut root = Box::new(HuffNode::new(None, 2));
+        root.left = Some(Box::new(HuffNode::new(Some('a'), 1)));
+        root.right = Some(Box::new(HuffNode::new(Some('a'), 1)));
+
+        let result = generate_prefix_table(Some(root));
+
+        assert!(matches!(result, Err(HuffError::DuplicateSymbol(0x61))));
+    }
+
+    #[test]
+    fn test_get_encoded_data_unknown_symbol() {
+        let mut freq: Freq = Freq::new();
+        freq.update("aab".as_bytes()).unwrap();
+        let root = generate_tree(&freq);
+        let prefix_table = generate_prefix_table(root).unwrap();
+        let test_file = Cursor::new("abc".as_bytes().to_vec());
+
+        let result = get_encoded_data(test_file, prefix_table);
+
+        assert!(matches!(result, Err(HuffError::UnknownSymbol(0x63))));
+    }
+
     // Lil utility function for printing u8 as bits
     fn _print_as_bytes(byte_vec: Vec<u8>) {
         for byte in byte_vec {
@@ -218,9 +313,9 @@ mod tests {
 
     #[test]
     fn test_header_generation_one_node() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaa".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
         let mut bw = BitWriter::new();
         let expected = vec![
@@ -231,7 +326,7 @@ mod tests {
             0b10000000,
         ];
 
-        generate_header(&root, &mut bw);
+        generate_header(&root, &mut bw).unwrap();
         let result = bw.get_vec().ok().unwrap();
 
         assert_eq!(result, expected);
@@ -239,9 +334,9 @@ mod tests {
 
     #[test]
     fn test_header_generation_two_nodes() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aab".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
         let mut bw = BitWriter::new();
         let expected = vec![
@@ -249,7 +344,7 @@ mod tests {
             0b00001100, 0b00100000,
         ];
 
-        generate_header(&root, &mut bw);
+        generate_header(&root, &mut bw).unwrap();
         let result = bw.get_vec().ok().unwrap();
 
         assert_eq!(result, expected);
@@ -257,9 +352,9 @@ mod tests {
 
     #[test]
     fn test_header_generation_three_nodes() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "abbccccc".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
         let mut bw = BitWriter::new();
         let expected = vec![
@@ -267,7 +362,7 @@ mod tests {
             0b00000110, 0b00101000, 0b00000000, 0b00000000, 0b00000011, 0b00011000,
         ];
 
-        generate_header(&root, &mut bw);
+        generate_header(&root, &mut bw).unwrap();
         let result = bw.get_vec().ok().unwrap();
 
         assert_eq!(result, expected);
@@ -275,14 +370,14 @@ mod tests {
 
     #[test]
     fn test_get_tree_header_with_size() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaa".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
         let expected_size = 5;
         let expected = vec![0b10000000, 0b00000000, 0b00000000, 0b00110000, 0b10000000];
 
-        let (header_size, header) = get_tree_header_with_size(&root);
+        let (header_size, header) = get_tree_header_with_size(&root).unwrap();
 
         assert_eq!(header_size, expected_size);
         assert_eq!(header, expected);
@@ -290,9 +385,9 @@ mod tests {
 
     #[test]
     fn test_get_tree_header_with_size_two_nodes() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aab".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
         let expected_size = 9;
         let expected = vec![
@@ -300,7 +395,7 @@ mod tests {
             0b00001100, 0b00100000,
         ];
 
-        let (header_size, header) = get_tree_header_with_size(&root);
+        let (header_size, header) = get_tree_header_with_size(&root).unwrap();
 
         assert_eq!(header_size, expected_size);
         assert_eq!(header, expected);
@@ -308,9 +403,9 @@ mod tests {
 
     #[test]
     fn test_get_tree_header_with_size_three_nodes() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "abbccccc".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
         let expected_size = 13;
         let expected = vec![
@@ -318,7 +413,7 @@ mod tests {
             0b00000110, 0b00101000, 0b00000000, 0b00000000, 0b00000011, 0b00011000,
         ];
 
-        let (header_size, header) = get_tree_header_with_size(&root);
+        let (header_size, header) = get_tree_header_with_size(&root).unwrap();
 
         assert_eq!(header, expected);
         assert_eq!(header_size, expected_size);
@@ -326,61 +421,88 @@ mod tests {
 
     #[test]
     fn test_get_encoded_data_with_header_one_distinct_char() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaa".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
         let test_file = Cursor::new(test_input.to_vec());
-        let expected = vec![0b00000000, 0b00000011];
+        let expected = vec![0b00000000];
 
-        let encoded_data = get_encoded_data(test_file, prefix_table);
+        let encoded_data = get_encoded_data(test_file, prefix_table).unwrap();
 
-        assert_eq!(encoded_data, expected);
+        assert_eq!(encoded_data.data, expected);
+        assert_eq!(encoded_data.symbol_count, 3);
+        assert_eq!(encoded_data.byte_count, 3);
     }
 
     #[test]
     fn test_get_encoded_data_with_header_two_distinct_chars() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aab".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
         let test_file = Cursor::new(test_input.to_vec());
-        let expected = vec![0b11000000, 0b00000011];
+        let expected = vec![0b11000000];
 
-        let encoded_data = get_encoded_data(test_file, prefix_table);
+        let encoded_data = get_encoded_data(test_file, prefix_table).unwrap();
 
-        assert_eq!(encoded_data, expected);
+        assert_eq!(encoded_data.data, expected);
     }
 
     #[test]
     fn test_get_encoded_data_with_header_three_distinct_chars() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaabcccc".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
         let test_file = Cursor::new(test_input.to_vec());
-        let expected = vec![0b11111110, 0b00000000, 0b00000100];
+        let expected = vec![0b11111110, 0b00000000];
 
-        let encoded_data = get_encoded_data(test_file, prefix_table);
+        let encoded_data = get_encoded_data(test_file, prefix_table).unwrap();
 
-        assert_eq!(encoded_data, expected);
+        assert_eq!(encoded_data.data, expected);
     }
 
     #[test]
     fn test_get_encoded_data_with_header_no_duplicates() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "abcd".as_bytes();
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
         let root = generate_tree(&freq);
-        let prefix_table = generate_prefix_table(root);
+        let prefix_table = generate_prefix_table(root).unwrap();
         let test_file = Cursor::new(test_input.to_vec());
-        let expected = vec![0b00011011, 0b00000000];
+        let expected = vec![0b00011011];
+
+        let encoded_data = get_encoded_data(test_file, prefix_table).unwrap();
+
+        assert_eq!(encoded_data.data, expected);
+    }
+
+    #[test]
+    fn test_get_encoded_data_counts() {
+        let mut freq: Freq = Freq::new();
+        let test_input = "héé".as_bytes();
+        freq.update(test_input).unwrap();
+        let prefix_table = generate_prefix_table(generate_tree(&freq)).unwrap();
+
+        let encoded_data = get_encoded_data(Cursor::new(test_input), prefix_table).unwrap();
+
+        assert_eq!(encoded_data.symbol_count, 3);
+        assert_eq!(encoded_data.byte_count, 5);
+        // a bit per symbol, so 5 bits of padding
+        assert_eq!(encoded_data.padding_bits, 5);
+    }
+
+    #[test]
+    fn test_get_encoded_data_empty() {
+        let prefix_table: HashMap<char, (u32, u8)> = HashMap::new();
 
-        let encoded_data = get_encoded_data(test_file, prefix_table);
+        let encoded_data = get_encoded_data(Cursor::new(Vec::new()), prefix_table).unwrap();
 
-        assert_eq!(encoded_data, expected);
+        assert!(encoded_data.data.is_empty());
+        assert_eq!(encoded_data.symbol_count, 0);
     }
 }
@@ -1,56 +1,194 @@
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::iter::Sum;
+use std::ops::Add;
 
 use std::collections::HashMap;
 
-pub struct Freq {
-    pub counter: HashMap<char, u32>,
+use super::huffio::read_exact_or;
+use super::symbol::{Alphabet, Symbol};
+use crate::error::HuffError;
+use crate::report::entropy;
+
+/// Every saved frequency table starts with these.
+const FREQ_MAGIC: [u8; 4] = *b"HFRQ";
+/// The frequency table version written by this crate. Version 1 had u32
+/// counts, and can still be read.
+const FREQ_VERSION: u8 = 2;
+/// The oldest frequency table version this crate can read.
+const MIN_FREQ_VERSION: u8 = 1;
+
+#[derive(Debug, Clone)]
+pub struct Freq<S = char> {
+    pub counter: HashMap<S, u64>,
     pub incomplete: Vec<u8>,
 }
 
-impl Freq {
-    pub fn new() -> Freq {
+impl<S: Symbol> Freq<S> {
+    pub fn new() -> Freq<S> {
         Freq {
             counter: HashMap::new(),
             incomplete: Vec::new(),
         }
     }
 
-    pub fn update(&mut self, chunk: &[u8]) {
-        let mut data = self.incomplete.clone();
-        data.extend_from_slice(&chunk);
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), HuffError> {
+        let counter = &mut self.counter;
 
-        let (valid, incomplete) = match std::str::from_utf8(&data) {
-            Ok(valid_str) => (valid_str, &[] as &[u8]),
-            Err(e) => {
-                let valid_up_to = e.valid_up_to();
-                let valid = &data[..valid_up_to];
-                let incomplete = &data[valid_up_to..];
-                (std::str::from_utf8(valid).unwrap(), incomplete)
-            }
-        };
-
-        self.incomplete = incomplete.to_vec();
-
-        for ch in valid.chars() {
-            self.counter
-                .entry(ch)
+        S::split(&mut self.incomplete, chunk, |symbol| {
+            counter
+                .entry(symbol)
                 .and_modify(|count| *count += 1)
                 .or_insert(1);
-        }
+            Ok(())
+        })
     }
 
-    pub fn count_chars(&mut self, file: &File) {
+    pub fn count_chars<R: Read>(&mut self, file: R) -> Result<(), HuffError> {
         let mut reader = BufReader::new(file);
         let mut buffer = [0; 1024];
 
-        while let Ok(bytes_read) = reader.read(&mut buffer) {
-            if bytes_read == 0 {
-                break;
-            }
+        loop {
+            let bytes_read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(bytes_read) => bytes_read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            self.update(&buffer[..bytes_read])?;
+        }
+
+        // whatever is left over never became a whole symbol
+        if !self.incomplete.is_empty() {
+            return Err(HuffError::InvalidUtf8);
+        }
+
+        Ok(())
+    }
+
+    /// Adds `count` to every one of `symbols`, whether it's been seen or not, so
+    /// a tree built from the counts has a code for each of them.
+    pub fn smooth<I: IntoIterator<Item = S>>(&mut self, symbols: I, count: u64) {
+        for symbol in symbols {
+            let total = self.counter.entry(symbol).or_insert(0);
+            *total = total.saturating_add(count);
+        }
+    }
+
+    /// Adds the counts from `other` to these, like counting its input here too.
+    /// Bytes of an unfinished symbol in `other` aren't carried over, and counts
+    /// stop at `u64::MAX` rather than wrapping.
+    pub fn merge(&mut self, other: &Freq<S>) {
+        for (&symbol, &count) in &other.counter {
+            let total = self.counter.entry(symbol).or_insert(0);
+            *total = total.saturating_add(count);
+        }
+    }
+
+    /// Shannon entropy of the counted symbols, in bits per symbol.
+    pub fn entropy(&self) -> f64 {
+        entropy(self.counter.values().copied())
+    }
+
+    /// Writes the counts as the magic bytes, a version byte and the alphabet
+    /// byte, then a u32 number of symbols followed by each symbol and its
+    /// u64 count, in symbol order so the same counts always save the same way.
+    pub fn save<W: Write>(&self, mut writer: W) -> Result<(), HuffError> {
+        writer.write_all(&FREQ_MAGIC)?;
+        writer.write_all(&[FREQ_VERSION, S::ALPHABET.to_byte()])?;
+        writer.write_all(&(self.counter.len() as u32).to_be_bytes())?;
+
+        let mut counts: Vec<_> = self.counter.iter().collect();
+        counts.sort_unstable();
+        let symbol_bytes = S::BITS as usize / 8;
+        for (symbol, count) in counts {
+            writer.write_all(&symbol.to_bits().to_be_bytes()[4 - symbol_bytes..])?;
+            writer.write_all(&count.to_be_bytes())?;
+        }
+        Ok(())
+    }
 
-            self.update(&buffer[..bytes_read]);
+    /// Reads counts written by [`Freq::save`]. They have to be for the same
+    /// alphabet as `S`.
+    pub fn load<R: Read>(mut reader: R) -> Result<Self, HuffError> {
+        let mut start = Vec::with_capacity(6);
+        (&mut reader).take(6).read_to_end(&mut start)?;
+        if start.is_empty() {
+            return Err(HuffError::EmptyInput);
         }
+        if !start.starts_with(&FREQ_MAGIC) {
+            return Err(HuffError::NotHuff);
+        }
+        let [_, _, _, _, version, alphabet] = start[..] else {
+            return Err(HuffError::TruncatedHeader);
+        };
+        if !(MIN_FREQ_VERSION..=FREQ_VERSION).contains(&version) {
+            return Err(HuffError::UnsupportedVersion(version));
+        }
+        let alphabet = Alphabet::from_byte(alphabet)?;
+        if alphabet != S::ALPHABET {
+            return Err(HuffError::AlphabetMismatch {
+                expected: S::ALPHABET,
+                actual: alphabet,
+            });
+        }
+
+        let mut len_buf = [0u8; 4];
+        read_exact_or(&mut reader, &mut len_buf, HuffError::TruncatedHeader)?;
+        let len = u32::from_be_bytes(len_buf);
+
+        let symbol_bytes = S::BITS as usize / 8;
+        let count_bytes = match version {
+            1 => 4,
+            _ => 8,
+        };
+        let mut freq = Freq::new();
+        for _ in 0..len {
+            let mut symbol_buf = [0u8; 4];
+            read_exact_or(
+                &mut reader,
+                &mut symbol_buf[4 - symbol_bytes..],
+                HuffError::TruncatedData,
+            )?;
+            let mut count_buf = [0u8; 8];
+            read_exact_or(
+                &mut reader,
+                &mut count_buf[8 - count_bytes..],
+                HuffError::TruncatedData,
+            )?;
+
+            let bits = u32::from_be_bytes(symbol_buf);
+            let symbol = S::from_bits(bits)?;
+            if freq
+                .counter
+                .insert(symbol, u64::from_be_bytes(count_buf))
+                .is_some()
+            {
+                return Err(HuffError::DuplicateSymbol(bits));
+            }
+        }
+        Ok(freq)
+    }
+}
+
+impl<S: Symbol> Add for Freq<S> {
+    type Output = Freq<S>;
+
+    fn add(mut self, other: Freq<S>) -> Freq<S> {
+        self.merge(&other);
+        self
+    }
+}
+
+impl<S: Symbol> Sum for Freq<S> {
+    fn sum<I: Iterator<Item = Freq<S>>>(iter: I) -> Freq<S> {
+        iter.fold(Freq::new(), Add::add)
+    }
+}
+
+impl<S: Symbol> Default for Freq<S> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -62,10 +200,10 @@ mod tests {
 
     #[test]
     fn test_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "Hello".as_bytes();
 
-        freq.update(&test_input);
+        freq.update(test_input).unwrap();
 
         assert_eq!(freq.counter.get(&'H').unwrap(), &1);
         assert_eq!(freq.counter.get(&'e').unwrap(), &1);
@@ -74,22 +212,186 @@ mod tests {
         assert_eq!(freq.counter.len(), 4);
     }
 
+    #[test]
+    fn test_smooth() {
+        let mut freq: Freq<u8> = Freq::new();
+        freq.update(b"aab").unwrap();
+
+        freq.smooth(0..=255, 1);
+
+        assert_eq!(freq.counter.len(), 256);
+        assert_eq!(freq.counter[&b'a'], 3);
+        assert_eq!(freq.counter[&b'b'], 2);
+        assert_eq!(freq.counter[&0], 1);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut freq: Freq = Freq::new();
+        freq.update("aab".as_bytes()).unwrap();
+        let mut other: Freq = Freq::new();
+        other.update("bc".as_bytes()).unwrap();
+
+        freq.merge(&other);
+
+        assert_eq!(freq.counter, HashMap::from([('a', 2), ('b', 2), ('c', 1)]));
+    }
+
+    #[test]
+    fn test_sum_matches_counting_everything() {
+        let shards = ["Hello, ", "привет", " world"];
+        let mut whole: Freq = Freq::new();
+        whole.update(shards.concat().as_bytes()).unwrap();
+
+        let summed: Freq = shards
+            .iter()
+            .map(|shard| {
+                let mut freq = Freq::new();
+                freq.update(shard.as_bytes()).unwrap();
+                freq
+            })
+            .sum();
+
+        assert_eq!(summed.counter, whole.counter);
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let mut chars: Freq = Freq::new();
+        chars.update(test_cases::SAMPLE_TEST.as_bytes()).unwrap();
+        let mut bytes: Freq<u8> = Freq::new();
+        bytes.update(&[0, 0xff, 0xff, b'a']).unwrap();
+
+        let mut saved = Vec::new();
+        chars.save(&mut saved).unwrap();
+        assert_eq!(
+            Freq::<char>::load(saved.as_slice()).unwrap().counter,
+            chars.counter
+        );
+
+        let mut saved = Vec::new();
+        bytes.save(&mut saved).unwrap();
+        assert_eq!(saved.len(), 6 + 4 + 3 * 9);
+        assert_eq!(
+            Freq::<u8>::load(saved.as_slice()).unwrap().counter,
+            bytes.counter
+        );
+    }
+
+    #[test]
+    fn test_load_v1() {
+        // version 1 had u32 counts
+        let saved = [
+            b"HFRQ".as_slice(),
+            &[1, 1],
+            &[0, 0, 0, 2],
+            &[b'a', 0, 0, 0, 2],
+            &[b'b', 0xff, 0xff, 0xff, 0xff],
+        ]
+        .concat();
+
+        let freq = Freq::<u8>::load(saved.as_slice()).unwrap();
+
+        assert_eq!(
+            freq.counter,
+            HashMap::from([(b'a', 2), (b'b', u32::MAX as u64)])
+        );
+        assert!(matches!(
+            Freq::<u8>::load(&saved[..saved.len() - 1]),
+            Err(HuffError::TruncatedData)
+        ));
+    }
+
+    #[test]
+    fn test_counts_above_u32_max() {
+        let mut freq: Freq<u8> = Freq::new();
+        freq.counter.insert(b'a', u32::MAX as u64);
+        let mut other = freq.clone();
+        other.counter.insert(b'b', 1);
+
+        let mut merged = freq + other;
+        assert_eq!(merged.counter[&b'a'], 2 * u32::MAX as u64);
+
+        let mut saved = Vec::new();
+        merged.save(&mut saved).unwrap();
+        assert_eq!(
+            Freq::<u8>::load(saved.as_slice()).unwrap().counter,
+            merged.counter
+        );
+
+        let mut huge: Freq<u8> = Freq::new();
+        huge.counter.insert(b'a', u64::MAX - 1);
+        merged.merge(&huge);
+        assert_eq!(merged.counter[&b'a'], u64::MAX);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let mut freq: Freq<u8> = Freq::new();
+        freq.update(b"aab").unwrap();
+        let mut saved = Vec::new();
+        freq.save(&mut saved).unwrap();
+
+        assert!(matches!(
+            Freq::<u8>::load(&[][..]),
+            Err(HuffError::EmptyInput)
+        ));
+        assert!(matches!(
+            Freq::<u8>::load(&b"HTRE\x01\x01"[..]),
+            Err(HuffError::NotHuff)
+        ));
+        assert!(matches!(
+            Freq::<u8>::load(&b"HFRQ\x03\x01"[..]),
+            Err(HuffError::UnsupportedVersion(3))
+        ));
+        assert!(matches!(
+            Freq::<u8>::load(&saved[..8]),
+            Err(HuffError::TruncatedHeader)
+        ));
+        assert!(matches!(
+            Freq::<u8>::load(&saved[..saved.len() - 1]),
+            Err(HuffError::TruncatedData)
+        ));
+        assert!(matches!(
+            Freq::<char>::load(saved.as_slice()),
+            Err(HuffError::AlphabetMismatch {
+                expected: Alphabet::Chars,
+                actual: Alphabet::Bytes
+            })
+        ));
+
+        let mut duplicate = saved.clone();
+        duplicate[19] = b'a';
+        assert!(matches!(
+            Freq::<u8>::load(duplicate.as_slice()),
+            Err(HuffError::DuplicateSymbol(0x61))
+        ));
+    }
+
+    #[test]
+    fn test_entropy() {
+        let mut freq: Freq = Freq::new();
+        freq.update("aabc".as_bytes()).unwrap();
+
+        assert_eq!(freq.entropy(), 1.5);
+    }
+
     #[test]
     fn test_empty_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "".as_bytes();
 
-        freq.update(&test_input);
+        freq.update(test_input).unwrap();
 
         assert!(freq.counter.is_empty());
     }
 
     #[test]
     fn test_unicode_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "привет".as_bytes();
 
-        freq.update(&test_input);
+        freq.update(test_input).unwrap();
 
         assert_eq!(freq.counter.get(&'п').unwrap(), &1);
         assert_eq!(freq.counter.get(&'р').unwrap(), &1);
@@ -101,10 +403,10 @@ mod tests {
 
     #[test]
     fn test_bigger_than_chunk_size_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaabb".as_bytes();
 
-        freq.update(&test_input);
+        freq.update(test_input).unwrap();
 
         assert_eq!(freq.counter.get(&'a').unwrap(), &4094);
         assert_eq!(freq.counter.get(&'b').unwrap(), &2);
@@ -112,10 +414,10 @@ mod tests {
 
     #[test]
     fn test_bigger_than_chunk_size_unicode_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "иииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииииипппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппппп".as_bytes();
 
-        freq.update(&test_input);
+        freq.update(test_input).unwrap();
 
         assert_eq!(freq.counter.get(&'и').unwrap(), &1024);
         assert_eq!(freq.counter.get(&'п').unwrap(), &1024);
@@ -123,9 +425,9 @@ mod tests {
 
     #[test]
     fn test_freq_sample_paragraph() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = test_cases::SAMPLE_TEST;
-        let expected_counter_state: HashMap<char, u32> = HashMap::from([
+        let expected_counter_state: HashMap<char, u64> = HashMap::from([
             (' ', 75),
             ('e', 47),
             ('t', 40),
@@ -165,8 +467,30 @@ mod tests {
             ('I', 1),
         ]);
 
-        freq.update(&test_input.as_bytes());
+        freq.update(test_input.as_bytes()).unwrap();
 
         assert_eq!(expected_counter_state, freq.counter);
     }
+
+    #[test]
+    fn test_bytes() {
+        let mut freq: Freq<u8> = Freq::new();
+        let test_input = [0xff, 0x00, 0xff, b'a'];
+
+        freq.update(&test_input).unwrap();
+
+        assert_eq!(freq.counter.get(&0xff).unwrap(), &2);
+        assert_eq!(freq.counter.get(&0x00).unwrap(), &1);
+        assert_eq!(freq.counter.get(&b'a').unwrap(), &1);
+    }
+
+    #[test]
+    fn test_count_chars_truncated_utf8() {
+        let mut freq: Freq<char> = Freq::new();
+        let test_input = "пр".as_bytes();
+
+        let result = freq.count_chars(&test_input[..3]);
+
+        assert!(matches!(result, Err(HuffError::InvalidUtf8)));
+    }
 }
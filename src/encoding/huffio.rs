@@ -1,44 +1,1146 @@
-use std::fs::File;
-use std::io::{self, BufWriter, Read, Write};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
 
-use super::decoding::{decode_data, decode_tree_header_with_size};
-use super::encoding::generate_prefix_table;
+use super::adaptive::decode_adaptive_data;
+use super::bitreader::BitReader;
+use super::crc32::Crc32;
+use super::decoding::{
+    decode_canonical_header_with_size, decode_data_with_strategy, DecodeStrategy,
+};
+use super::symbol::{Alphabet, Symbol};
+use super::table_decoder::DecodeTable;
+use super::tree::HuffmanTree;
+use crate::error::HuffError;
 
-pub fn write_size_header(file: &File, size: u32) -> io::Result<()> {
-    let mut writer = BufWriter::new(file);
-    writer.write_all(&size.to_be_bytes()).unwrap();
-    writer.flush().unwrap();
+/// How the code table is stored in the header.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// The whole tree in preorder, see `HuffmanTree::write_header`.
+    Tree,
+    /// Just the symbols and their code lengths, see `generate_canonical_header`.
+    #[default]
+    Canonical,
+}
+
+/// The low bits of the file header's flags byte. bit 0 is the alphabet and
+/// bit 1 is set when the header is canonical.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+    pub alphabet: Alphabet,
+    pub header: HeaderFormat,
+}
+
+const CANONICAL_BIT: u8 = 0b10;
+const MODE_BITS: u8 = CANONICAL_BIT | 1;
+
+impl Mode {
+    pub fn to_byte(self) -> u8 {
+        let header_bits = match self.header {
+            HeaderFormat::Tree => 0,
+            HeaderFormat::Canonical => CANONICAL_BIT,
+        };
+        self.alphabet.to_byte() | header_bits
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self, HuffError> {
+        if byte & !MODE_BITS != 0 {
+            return Err(HuffError::InvalidMode(byte));
+        }
+
+        let header = match byte & CANONICAL_BIT {
+            0 => HeaderFormat::Tree,
+            _ => HeaderFormat::Canonical,
+        };
+        Ok(Mode {
+            alphabet: Alphabet::from_byte(byte & 1)?,
+            header,
+        })
+    }
+}
+
+/// Each block starts with one of these. After the file header the output is a
+/// run of huffman and stored blocks, then an end block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
+    /// Nothing follows, the stream is done.
+    End,
+    /// A u32 header size, the header, u64 symbol and byte counts, a u64 data
+    /// size, then the data coded with that header.
+    Huffman,
+    /// A u64 size, then that many bytes of input as is. Used when coding the
+    /// block would make it bigger.
+    Stored,
+    /// Like a huffman block, but without a header of its own. The data is coded
+    /// with the header from the last huffman block.
+    Reuse,
+    /// Laid out like a reuse block, but coded with an adaptive tree that starts
+    /// out empty, see `AdaptiveTree`.
+    Adaptive,
+    /// Laid out like a reuse block, but coded with the shared tree the file
+    /// header names. It doesn't replace the last huffman block's tree.
+    Shared,
+}
+
+impl BlockType {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            BlockType::End => 0,
+            BlockType::Huffman => 1,
+            BlockType::Stored => 2,
+            BlockType::Reuse => 3,
+            BlockType::Adaptive => 4,
+            BlockType::Shared => 5,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self, HuffError> {
+        match byte {
+            0 => Ok(BlockType::End),
+            1 => Ok(BlockType::Huffman),
+            2 => Ok(BlockType::Stored),
+            3 => Ok(BlockType::Reuse),
+            4 => Ok(BlockType::Adaptive),
+            5 => Ok(BlockType::Shared),
+            _ => Err(HuffError::InvalidBlockType(byte)),
+        }
+    }
+
+    /// The first format version that has this kind of block.
+    pub fn since_version(self) -> u8 {
+        match self {
+            BlockType::End | BlockType::Huffman => 1,
+            BlockType::Stored => 3,
+            BlockType::Reuse => 4,
+            BlockType::Adaptive => 5,
+            BlockType::Shared => 6,
+        }
+    }
+}
+
+/// Every huff file starts with these.
+pub const MAGIC: [u8; 4] = *b"HUFF";
+/// The format version written by this crate. Every change to the layout gets
+/// a new version, so older readers turn a file down instead of tripping over
+/// something they don't know. Every version back to the first can be read.
+///
+/// 1. The first one. The data of a huffman block has no counts in front of it,
+///    and ends with a byte saying how many bits of the byte before it are used.
+/// 2. Symbol and byte counts in front of the data of every huffman block.
+/// 3. Stored blocks.
+/// 4. Reuse blocks.
+/// 5. Adaptive blocks.
+/// 6. Shared trees, with the shared tree flag and shared blocks.
+pub const FORMAT_VERSION: u8 = 6;
+/// The oldest format version this crate can read.
+pub const MIN_FORMAT_VERSION: u8 = 1;
+
+const CHECKSUM_BIT: u8 = 0b100;
+const LENGTH_BIT: u8 = 0b1000;
+const SHARED_TREE_BIT: u8 = 0b10000;
+
+/// What comes before the blocks: the magic bytes, the format version, a flags
+/// byte, the original length as a u64 if the flags say it's there, and the
+/// fingerprint of a shared tree as a u32 if the file was coded with one.
+///
+/// When the checksum flag is set, the end block is followed by a trailer
+/// holding the CRC-32 of the original data, see `write_trailer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHeader {
+    pub version: u8,
+    pub mode: Mode,
+    /// How many bytes the input was. Only known up front when the whole input
+    /// was available, so streamed output leaves it out.
+    pub original_length: Option<u64>,
+    /// Whether there's a checksum in the trailer.
+    pub checksum: bool,
+    /// The fingerprint of the [`SharedTree`](crate::SharedTree) the blocks
+    /// start out with, if there was one. The decoder needs the same tree.
+    pub shared_tree: Option<u32>,
+}
+
+impl FileHeader {
+    pub fn new(mode: Mode, original_length: Option<u64>, checksum: bool) -> Self {
+        FileHeader {
+            version: FORMAT_VERSION,
+            mode,
+            original_length,
+            checksum,
+            shared_tree: None,
+        }
+    }
+
+    pub fn flags(&self) -> u8 {
+        let length_bit = match self.original_length {
+            Some(_) => LENGTH_BIT,
+            None => 0,
+        };
+        let checksum_bit = match self.checksum {
+            true => CHECKSUM_BIT,
+            false => 0,
+        };
+        let shared_tree_bit = match self.shared_tree {
+            Some(_) => SHARED_TREE_BIT,
+            None => 0,
+        };
+        self.mode.to_byte() | checksum_bit | length_bit | shared_tree_bit
+    }
+
+    /// Checks that `decoded` bytes came out, if we know how many to expect.
+    pub fn check_length(&self, decoded: u64) -> Result<(), HuffError> {
+        match self.original_length {
+            Some(expected) if expected != decoded => Err(HuffError::LengthMismatch {
+                expected,
+                actual: decoded,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn write_file_header<W: Write>(mut writer: W, header: &FileHeader) -> Result<(), HuffError> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[header.version, header.flags()])?;
+    if let Some(original_length) = header.original_length {
+        writer.write_all(&original_length.to_be_bytes())?;
+    }
+    if let Some(fingerprint) = header.shared_tree {
+        writer.write_all(&fingerprint.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads and checks a header written by `write_file_header`.
+pub fn read_file_header<R: Read>(input: &mut R) -> Result<FileHeader, HuffError> {
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    input.take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+    if magic.is_empty() {
+        return Err(HuffError::EmptyInput);
+    }
+    if magic != MAGIC {
+        return Err(HuffError::NotHuff);
+    }
+
+    let mut version_buf = [0u8; 1];
+    read_exact_or(input, &mut version_buf, HuffError::TruncatedHeader)?;
+    match version_buf[0] {
+        version @ MIN_FORMAT_VERSION..=FORMAT_VERSION => read_flags_and_length(input, version),
+        version => Err(HuffError::UnsupportedVersion(version)),
+    }
+}
+
+fn read_flags_and_length<R: Read>(input: &mut R, version: u8) -> Result<FileHeader, HuffError> {
+    let mut flags_buf = [0u8; 1];
+    read_exact_or(input, &mut flags_buf, HuffError::TruncatedHeader)?;
+    let flags = flags_buf[0];
+    let known_flags = match version {
+        1..=5 => MODE_BITS | CHECKSUM_BIT | LENGTH_BIT,
+        _ => MODE_BITS | CHECKSUM_BIT | LENGTH_BIT | SHARED_TREE_BIT,
+    };
+    if flags & !known_flags != 0 {
+        return Err(HuffError::InvalidMode(flags));
+    }
+
+    let original_length = if flags & LENGTH_BIT != 0 {
+        let mut length_buf = [0u8; 8];
+        read_exact_or(input, &mut length_buf, HuffError::TruncatedHeader)?;
+        Some(u64::from_be_bytes(length_buf))
+    } else {
+        None
+    };
+
+    let shared_tree = if flags & SHARED_TREE_BIT != 0 {
+        let mut fingerprint_buf = [0u8; 4];
+        read_exact_or(input, &mut fingerprint_buf, HuffError::TruncatedHeader)?;
+        Some(u32::from_be_bytes(fingerprint_buf))
+    } else {
+        None
+    };
+
+    Ok(FileHeader {
+        version,
+        mode: Mode::from_byte(flags & MODE_BITS)?,
+        original_length,
+        checksum: flags & CHECKSUM_BIT != 0,
+        shared_tree,
+    })
+}
+
+/// Goes right after the end block. Holds the CRC-32 of the original data
+/// if the header says there's a checksum, and is empty otherwise.
+pub fn write_trailer<W: Write>(
+    mut writer: W,
+    header: &FileHeader,
+    checksum: u32,
+) -> Result<(), HuffError> {
+    if header.checksum {
+        writer.write_all(&checksum.to_be_bytes())?;
+    }
     Ok(())
 }
 
-pub fn write_compressed_data(file: &File, data: Vec<u8>) -> io::Result<()> {
-    let mut writer = BufWriter::new(file);
-    writer.write_all(&data).unwrap();
-    writer.flush().unwrap();
+/// Reads the trailer once the end block has come up, and checks the length and
+/// checksum of what was decoded against what the encoder recorded.
+pub fn check_trailer<R: Read>(
+    input: &mut R,
+    header: &FileHeader,
+    decoded: u64,
+    checksum: u32,
+) -> Result<(), HuffError> {
+    header.check_length(decoded)?;
+
+    if header.checksum {
+        let mut checksum_buf = [0u8; 4];
+        read_exact_or(input, &mut checksum_buf, HuffError::TruncatedData)?;
+        let expected = u32::from_be_bytes(checksum_buf);
+        if expected != checksum {
+            return Err(HuffError::ChecksumMismatch {
+                expected,
+                actual: checksum,
+            });
+        }
+    }
+
     Ok(())
 }
 
-pub fn decompress_data(file: &mut File, output_filename: String) -> io::Result<()> {
-    let mut output_file = File::create(output_filename).unwrap();
+pub fn write_size_header<W: Write>(mut writer: W, size: u32) -> Result<(), HuffError> {
+    writer.write_all(&size.to_be_bytes())?;
+    Ok(())
+}
+
+pub fn write_block_type<W: Write>(mut writer: W, block_type: BlockType) -> Result<(), HuffError> {
+    writer.write_all(&[block_type.to_byte()])?;
+    Ok(())
+}
+
+/// How many symbols a block decodes to and how many bytes that comes to.
+pub fn write_counts_header<W: Write>(
+    mut writer: W,
+    symbol_count: u64,
+    byte_count: u64,
+) -> Result<(), HuffError> {
+    writer.write_all(&symbol_count.to_be_bytes())?;
+    writer.write_all(&byte_count.to_be_bytes())?;
+    Ok(())
+}
+
+pub fn write_data_size_header<W: Write>(mut writer: W, size: u64) -> Result<(), HuffError> {
+    writer.write_all(&size.to_be_bytes())?;
+    Ok(())
+}
+
+pub fn write_compressed_data<W: Write>(mut writer: W, data: Vec<u8>) -> Result<(), HuffError> {
+    writer.write_all(&data)?;
+    Ok(())
+}
 
-    // 1. read four bytes to get header size
+/// read_exact, but running out of input is reported as `on_eof` rather than an i/o error
+pub(crate) fn read_exact_or<R: Read>(
+    input: &mut R,
+    buf: &mut [u8],
+    on_eof: HuffError,
+) -> Result<(), HuffError> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => on_eof,
+        _ => HuffError::Io(e),
+    })
+}
+
+pub fn decompress_data<R: Read, W: Write>(input: &mut R, output: W) -> Result<(), HuffError> {
+    decompress_data_with_strategy(input, output, DecodeStrategy::default())
+}
+
+pub fn decompress_data_with_strategy<R: Read, W: Write>(
+    input: &mut R,
+    mut output: W,
+    strategy: DecodeStrategy,
+) -> Result<(), HuffError> {
+    let header = read_file_header(input)?;
+    // there's no way to pass a shared tree in here, that takes a Decoder
+    if let Some(fingerprint) = header.shared_tree {
+        return Err(HuffError::MissingSharedTree(fingerprint));
+    }
+    let mut checksum = Crc32::new();
+    let mut decoded = 0;
+
+    // each block is decoded on its own so it can be checksummed before it's written
+    let mut block = Vec::new();
+    let mut previous_header = None;
+    while let Some(block_len) = decompress_next_block(
+        input,
+        &mut block,
+        &header,
+        strategy,
+        &mut previous_header,
+        None,
+    )? {
+        checksum.update(&block);
+        output.write_all(&block)?;
+        block.clear();
+        decoded += block_len;
+    }
+
+    check_trailer(input, &header, decoded, checksum.value())
+}
+
+/// Decodes the next block from `input` into `output` and returns how many bytes
+/// it came to, or None once the end block has been read. `shared_header` is
+/// the header of the shared tree the file header asks for, if any.
+pub fn decompress_next_block<R: Read, W: Write>(
+    input: &mut R,
+    mut output: W,
+    file_header: &FileHeader,
+    strategy: DecodeStrategy,
+    previous_header: &mut Option<Vec<u8>>,
+    shared_header: Option<&[u8]>,
+) -> Result<Option<u64>, HuffError> {
+    let mode = file_header.mode;
+    let block_len = match read_block_type(input, file_header)? {
+        BlockType::End => return Ok(None),
+        BlockType::Huffman if file_header.version == 1 => {
+            let header = previous_header.insert(read_block_header(input)?);
+            let decoded = decode_v1_block(mode, header, &read_sized_data(input)?)?;
+            output.write_all(&decoded)?;
+            decoded.len() as u64
+        }
+        BlockType::Huffman => {
+            let header = previous_header.insert(read_block_header(input)?);
+            decompress_block(input, output, mode, header, strategy)?
+        }
+        BlockType::Reuse => {
+            let header = previous_header
+                .as_deref()
+                .ok_or(HuffError::NoPreviousTree)?;
+            decompress_block(input, output, mode, header, strategy)?
+        }
+        BlockType::Shared => {
+            // the file header didn't ask for a shared tree, so there's none to use
+            let header =
+                shared_header.ok_or(HuffError::InvalidBlockType(BlockType::Shared.to_byte()))?;
+            decompress_block(input, output, mode, header, strategy)?
+        }
+        BlockType::Stored => copy_stored_block(input, output)?,
+        BlockType::Adaptive => decompress_adaptive_block(input, output, mode.alphabet)?,
+    };
+
+    Ok(Some(block_len))
+}
+
+/// What a block holds, as far as can be told without decoding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    pub block_type: BlockType,
+    /// How many bytes the block takes up, including its type byte.
+    pub compressed_size: u64,
+    /// How many bytes it decodes to.
+    pub original_size: u64,
+}
+
+/// Reads past the next block without decoding it, or returns None once the
+/// end block has been read. Version 1 huffman blocks don't say how much they
+/// decode to, so those do get decoded.
+pub fn read_block_info<R: Read>(
+    input: &mut R,
+    file_header: &FileHeader,
+) -> Result<Option<BlockInfo>, HuffError> {
+    let block_type = read_block_type(input, file_header)?;
+
+    let (framing, original_size, data_size) = match block_type {
+        BlockType::End => return Ok(None),
+        BlockType::Huffman if file_header.version == 1 => {
+            let header = read_block_header(input)?;
+            let data = read_sized_data(input)?;
+            let decoded = decode_v1_block(file_header.mode, &header, &data)?;
+            return Ok(Some(BlockInfo {
+                block_type,
+                compressed_size: 1 + 4 + header.len() as u64 + 8 + data.len() as u64,
+                original_size: decoded.len() as u64,
+            }));
+        }
+        BlockType::Stored => {
+            let size = read_u64(input)?;
+            (8, size, size)
+        }
+        BlockType::Huffman | BlockType::Reuse | BlockType::Adaptive | BlockType::Shared => {
+            let header_size = match block_type {
+                BlockType::Huffman => 4 + read_block_header(input)?.len() as u64,
+                _ => 0,
+            };
+            let _symbol_count = read_u64(input)?;
+            let byte_count = read_u64(input)?;
+            (header_size + 24, byte_count, read_u64(input)?)
+        }
+    };
+
+    let skipped = io::copy(&mut input.take(data_size), &mut io::sink())?;
+    if skipped != data_size {
+        return Err(HuffError::TruncatedData);
+    }
+
+    Ok(Some(BlockInfo {
+        block_type,
+        compressed_size: 1 + framing + data_size,
+        original_size,
+    }))
+}
+
+/// Reads a u32 header size, then the header.
+pub(crate) fn read_block_header<R: Read>(input: &mut R) -> Result<Vec<u8>, HuffError> {
     let mut header_size_buf = [0u8; 4];
-    file.read_exact(&mut header_size_buf).unwrap();
+    read_exact_or(input, &mut header_size_buf, HuffError::TruncatedHeader)?;
     let header_size = u32::from_be_bytes(header_size_buf);
-    println!("header size: {}", header_size);
 
-    // 2. read header and create prefix table
-    let mut header_buf = vec![0u8; header_size as usize];
-    file.read_exact(&mut header_buf).unwrap();
-    let tree = decode_tree_header_with_size(&header_buf);
-    let prefix_table = generate_prefix_table(tree);
+    // read through take() so a corrupt size can't make us allocate gigabytes up front
+    let mut header_buf = Vec::new();
+    input
+        .take(header_size as u64)
+        .read_to_end(&mut header_buf)?;
+    if header_buf.len() != header_size as usize {
+        return Err(HuffError::TruncatedHeader);
+    }
+    Ok(header_buf)
+}
+
+/// Turns a block header back into the prefix table it was written from.
+pub fn read_prefix_table<S: Symbol>(
+    header_format: HeaderFormat,
+    header: &[u8],
+) -> Result<HashMap<S, (u32, u8)>, HuffError> {
+    match header_format {
+        HeaderFormat::Tree => HuffmanTree::<S>::from_header(header)?.prefix_table(),
+        HeaderFormat::Canonical => decode_canonical_header_with_size::<S>(header),
+    }
+}
+
+fn decompress_block<R: Read, W: Write>(
+    input: &mut R,
+    output: W,
+    mode: Mode,
+    header: &[u8],
+    strategy: DecodeStrategy,
+) -> Result<u64, HuffError> {
+    match mode.alphabet {
+        Alphabet::Chars => {
+            let prefix_table = read_prefix_table::<char>(mode.header, header)?;
+            decode_block_data(input, output, prefix_table, strategy)
+        }
+        Alphabet::Bytes => {
+            let prefix_table = read_prefix_table::<u8>(mode.header, header)?;
+            decode_block_data(input, output, prefix_table, strategy)
+        }
+    }
+}
+
+/// Reads the counts and data that come after the header, or straight after the
+/// type byte when the block reuses the last header.
+fn decode_block_data<S: Symbol, R: Read, W: Write>(
+    input: &mut R,
+    output: W,
+    prefix_table: HashMap<S, (u32, u8)>,
+    strategy: DecodeStrategy,
+) -> Result<u64, HuffError> {
+    let (symbol_count, byte_count, data_buf) = read_block_data(input)?;
+    let data = decode_data_with_strategy(&data_buf, prefix_table, symbol_count, strategy)?;
+    write_symbols(output, data, byte_count)
+}
+
+fn decompress_adaptive_block<R: Read, W: Write>(
+    input: &mut R,
+    output: W,
+    alphabet: Alphabet,
+) -> Result<u64, HuffError> {
+    let (symbol_count, byte_count, data_buf) = read_block_data(input)?;
+    match alphabet {
+        Alphabet::Chars => {
+            let data = decode_adaptive_data::<char>(&data_buf, symbol_count)?;
+            write_symbols(output, data, byte_count)
+        }
+        Alphabet::Bytes => {
+            let data = decode_adaptive_data::<u8>(&data_buf, symbol_count)?;
+            write_symbols(output, data, byte_count)
+        }
+    }
+}
+
+/// Reads the type byte of the next block, and checks the file's version has
+/// that kind of block.
+fn read_block_type<R: Read>(
+    input: &mut R,
+    file_header: &FileHeader,
+) -> Result<BlockType, HuffError> {
+    let mut block_type_buf = [0u8; 1];
+    read_exact_or(input, &mut block_type_buf, HuffError::TruncatedData)?;
+    let block_type = BlockType::from_byte(block_type_buf[0])?;
+    if block_type.since_version() > file_header.version {
+        return Err(HuffError::InvalidBlockType(block_type_buf[0]));
+    }
+    Ok(block_type)
+}
+
+/// Reads how many symbols there are, how many bytes they decode to, and the
+/// coded data itself.
+fn read_block_data<R: Read>(input: &mut R) -> Result<(u64, u64, Vec<u8>), HuffError> {
+    let symbol_count = read_u64(input)?;
+    let byte_count = read_u64(input)?;
+    Ok((symbol_count, byte_count, read_sized_data(input)?))
+}
+
+/// Reads a u64 size, then that many bytes.
+fn read_sized_data<R: Read>(input: &mut R) -> Result<Vec<u8>, HuffError> {
+    let data_size = read_u64(input)?;
 
-    // 3. decode data and write to a file
     let mut data_buf: Vec<u8> = Vec::new();
-    file.read_to_end(&mut data_buf).unwrap();
-    let data = decode_data(&data_buf, prefix_table);
-    let data_str: String = data.iter().collect();
-    output_file.write_all(data_str.as_bytes()).unwrap();
+    input.take(data_size).read_to_end(&mut data_buf)?;
+    if data_buf.len() as u64 != data_size {
+        return Err(HuffError::TruncatedData);
+    }
+    Ok(data_buf)
+}
 
-    Ok(())
+/// Decodes the data of a version 1 huffman block coded with `header`. There's
+/// no symbol count, so it decodes until it runs out of bits, and the last byte
+/// says how many bits of the byte before it are used, 0 meaning all of them.
+fn decode_v1_block(mode: Mode, header: &[u8], data: &[u8]) -> Result<Vec<u8>, HuffError> {
+    let mut decoded = Vec::new();
+    match mode.alphabet {
+        Alphabet::Chars => {
+            let prefix_table = read_prefix_table::<char>(mode.header, header)?;
+            for symbol in decode_v1_data(data, &prefix_table)? {
+                symbol.write_to(&mut decoded);
+            }
+        }
+        Alphabet::Bytes => {
+            let prefix_table = read_prefix_table::<u8>(mode.header, header)?;
+            for symbol in decode_v1_data(data, &prefix_table)? {
+                symbol.write_to(&mut decoded);
+            }
+        }
+    }
+    Ok(decoded)
+}
+
+fn decode_v1_data<S: Symbol>(
+    data: &[u8],
+    prefix_table: &HashMap<S, (u32, u8)>,
+) -> Result<Vec<S>, HuffError> {
+    // at least one byte of data and the byte saying how much of it is used
+    let (data, used) = match data {
+        [data @ .., used] if !data.is_empty() => (data, *used),
+        _ => return Err(HuffError::TruncatedData),
+    };
+    let used = match used {
+        0 => 8,
+        1..=7 => used as usize,
+        _ => return Err(HuffError::TrailingByteMismatch(used)),
+    };
+    let total_bits = (data.len() - 1) * 8 + used;
+
+    let table = DecodeTable::new(prefix_table)?;
+    let mut br = BitReader::new(data.to_vec());
+    let mut symbols = Vec::new();
+    while br.bits_read() < total_bits {
+        let available = total_bits - br.bits_read();
+        symbols.push(table.decode_symbol(&mut br, available)?);
+    }
+    Ok(symbols)
+}
+
+/// Writes out decoded symbols, after checking they come to `byte_count` bytes.
+fn write_symbols<S: Symbol, W: Write>(
+    mut output: W,
+    data: Vec<S>,
+    byte_count: u64,
+) -> Result<u64, HuffError> {
+    // a symbol is at most 4 bytes, so this bounds what a corrupt count can ask for
+    let mut decoded = Vec::with_capacity(byte_count.min(data.len() as u64 * 4) as usize);
+    for symbol in data {
+        symbol.write_to(&mut decoded);
+    }
+    if decoded.len() as u64 != byte_count {
+        return Err(HuffError::LengthMismatch {
+            expected: byte_count,
+            actual: decoded.len() as u64,
+        });
+    }
+    output.write_all(&decoded)?;
+
+    Ok(byte_count)
+}
+
+fn copy_stored_block<R: Read, W: Write>(input: &mut R, mut output: W) -> Result<u64, HuffError> {
+    let size = read_u64(input)?;
+    let copied = io::copy(&mut input.take(size), &mut output)?;
+    if copied != size {
+        return Err(HuffError::TruncatedData);
+    }
+    Ok(size)
+}
+
+fn read_u64<R: Read>(input: &mut R) -> Result<u64, HuffError> {
+    let mut buf = [0u8; 8];
+    read_exact_or(input, &mut buf, HuffError::TruncatedData)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // the magic and version, in front of the flags byte and blocks
+    fn huff_file(flags_and_blocks: Vec<u8>) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        file.push(FORMAT_VERSION);
+        file.extend(flags_and_blocks);
+        file
+    }
+
+    #[test]
+    fn test_decompress_empty_input() {
+        let mut input = Cursor::new(Vec::new());
+
+        let result = decompress_data(&mut input, Vec::new());
+
+        assert!(matches!(result, Err(HuffError::EmptyInput)));
+    }
+
+    #[test]
+    fn test_decompress_invalid_mode() {
+        let mut input = Cursor::new(huff_file(vec![42, 0, 0, 0, 0]));
+
+        let result = decompress_data(&mut input, Vec::new());
+
+        assert!(matches!(result, Err(HuffError::InvalidMode(42))));
+    }
+
+    #[test]
+    fn test_mode_byte_round_trip() {
+        for alphabet in [Alphabet::Chars, Alphabet::Bytes] {
+            for header in [HeaderFormat::Tree, HeaderFormat::Canonical] {
+                let mode = Mode { alphabet, header };
+                assert_eq!(Mode::from_byte(mode.to_byte()).unwrap(), mode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decompress_canonical_bytes_mode() {
+        // a canonical header with 0x00 and 0xff at one bit each, then the data 0xff 0x00 0xff
+        let mut input = Cursor::new(huff_file(vec![
+            3, 1, 0, 0, 0, 4, 0b01110000, 0b00000000, 0b11111111, 0b00000000, 0, 0, 0, 0, 0, 0, 0,
+            3, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 1, 0b10100000, 0,
+        ]));
+        let mut output = Vec::new();
+
+        decompress_data(&mut input, &mut output).unwrap();
+
+        assert_eq!(output, vec![0xff, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_decompress_bytes_mode() {
+        // a byte mode tree with two leaves, 0x00 and 0xff, then the data 0xff 0x00 0xff
+        let mut input = Cursor::new(huff_file(vec![1]));
+        input.get_mut().extend(BYTES_BLOCK);
+        input.get_mut().push(0);
+        let mut output = Vec::new();
+
+        decompress_data(&mut input, &mut output).unwrap();
+
+        assert_eq!(output, vec![0xff, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_decompress_truncated_size_header() {
+        let mut input = Cursor::new(huff_file(vec![0, 1, 0, 0]));
+
+        let result = decompress_data(&mut input, Vec::new());
+
+        assert!(matches!(result, Err(HuffError::TruncatedHeader)));
+    }
+
+    #[test]
+    fn test_decompress_truncated_tree_header() {
+        // says the header is 5 bytes long but only has 2
+        let mut input = Cursor::new(huff_file(vec![0, 1, 0, 0, 0, 5, 0b10000000, 0]));
+
+        let result = decompress_data(&mut input, Vec::new());
+
+        assert!(matches!(result, Err(HuffError::TruncatedHeader)));
+    }
+
+    #[test]
+    fn test_decompress_invalid_scalar_value() {
+        // a single leaf holding 0xD800, which is a surrogate and not a char
+        let mut input = Cursor::new(huff_file(vec![
+            0, 1, 0, 0, 0, 5, 0b10000000, 0b00000000, 0b01101100, 0b00000000, 0b00000000,
+        ]));
+
+        let result = decompress_data(&mut input, Vec::new());
+
+        assert!(matches!(result, Err(HuffError::InvalidScalarValue(0xD800))));
+    }
+
+    // a byte mode block with a tree of two leaves, 0x00 and 0xff, then three
+    // symbols of data for 0xff 0x00 0xff
+    const BYTES_BLOCK: [u8; 33] = [
+        1, 0, 0, 0, 3, 0b01000000, 0b00111111, 0b11100000, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0,
+        0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 1, 0b10100000,
+    ];
+
+    // BYTES_BLOCK with its symbol count swapped for `count`
+    fn bytes_block_with_count(count: u8) -> Vec<u8> {
+        let mut block = BYTES_BLOCK.to_vec();
+        block[15] = count;
+        block
+    }
+
+    #[test]
+    fn test_decompress_too_many_symbols() {
+        // 9 one bit symbols can't fit in a single byte of data
+        let mut input = huff_file(vec![1]);
+        input.extend(bytes_block_with_count(9));
+        input.push(0);
+
+        let result = decompress_data(&mut Cursor::new(input), Vec::new());
+
+        assert!(matches!(result, Err(HuffError::TruncatedData)));
+    }
+
+    #[test]
+    fn test_decompress_stops_at_symbol_count() {
+        // the rest of the data byte would decode to more symbols if we didn't stop
+        let mut input = huff_file(vec![1]);
+        let mut block = bytes_block_with_count(2);
+        block[23] = 2;
+        input.extend(block);
+        input.push(0);
+        let mut output = Vec::new();
+
+        decompress_data(&mut Cursor::new(input), &mut output).unwrap();
+
+        assert_eq!(output, vec![0xff, 0x00]);
+    }
+
+    #[test]
+    fn test_decompress_wrong_byte_count() {
+        let mut input = huff_file(vec![1]);
+        let mut block = BYTES_BLOCK.to_vec();
+        block[23] = 4;
+        input.extend(block);
+        input.push(0);
+
+        let result = decompress_data(&mut Cursor::new(input), Vec::new());
+
+        assert!(matches!(
+            result,
+            Err(HuffError::LengthMismatch {
+                expected: 4,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_decompress_several_blocks() {
+        let mut input = huff_file(vec![1]);
+        input.extend(BYTES_BLOCK);
+        input.extend(BYTES_BLOCK);
+        input.push(0);
+        let mut output = Vec::new();
+
+        decompress_data(&mut Cursor::new(input), &mut output).unwrap();
+
+        assert_eq!(output, vec![0xff, 0x00, 0xff, 0xff, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_decompress_no_blocks() {
+        let mut output = Vec::new();
+
+        decompress_data(&mut Cursor::new(huff_file(vec![1, 0])), &mut output).unwrap();
+
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_decompress_missing_end_block() {
+        let mut input = huff_file(vec![1]);
+        input.extend(BYTES_BLOCK);
+
+        let result = decompress_data(&mut Cursor::new(input), Vec::new());
+
+        assert!(matches!(result, Err(HuffError::TruncatedData)));
+    }
+
+    #[test]
+    fn test_decompress_truncated_block_data() {
+        let mut input = huff_file(vec![1]);
+        input.extend(&BYTES_BLOCK[..BYTES_BLOCK.len() - 1]);
+
+        let result = decompress_data(&mut Cursor::new(input), Vec::new());
+
+        assert!(matches!(result, Err(HuffError::TruncatedData)));
+    }
+
+    #[test]
+    fn test_decompress_stored_block() {
+        let mut input = huff_file(vec![1, 2, 0, 0, 0, 0, 0, 0, 0, 3, b'h', b'e', b'y']);
+        input.extend(BYTES_BLOCK);
+        input.push(0);
+        let mut output = Vec::new();
+
+        decompress_data(&mut Cursor::new(input), &mut output).unwrap();
+
+        assert_eq!(output, vec![b'h', b'e', b'y', 0xff, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_decompress_truncated_stored_block() {
+        let input = huff_file(vec![1, 2, 0, 0, 0, 0, 0, 0, 0, 3, b'h', b'e']);
+
+        let result = decompress_data(&mut Cursor::new(input), Vec::new());
+
+        assert!(matches!(result, Err(HuffError::TruncatedData)));
+    }
+
+    #[test]
+    fn test_decompress_reuse_block() {
+        // the same three symbols again, coded with the tree from BYTES_BLOCK
+        let mut input = huff_file(vec![1]);
+        input.extend(BYTES_BLOCK);
+        input.push(3);
+        input.extend(&BYTES_BLOCK[8..]);
+        input.push(0);
+        let mut output = Vec::new();
+
+        decompress_data(&mut Cursor::new(input), &mut output).unwrap();
+
+        assert_eq!(output, vec![0xff, 0x00, 0xff, 0xff, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_decompress_reuse_without_tree() {
+        let mut input = huff_file(vec![1, 3]);
+        input.extend(&BYTES_BLOCK[8..]);
+        input.push(0);
+
+        let result = decompress_data(&mut Cursor::new(input), Vec::new());
+
+        assert!(matches!(result, Err(HuffError::NoPreviousTree)));
+    }
+
+    #[test]
+    fn test_decompress_adaptive_block() {
+        // 0xff raw, then the new symbol escape (0) and 0x00 raw, then 0xff's code (11)
+        let mut input = huff_file(vec![1, 4]);
+        input.extend([0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 3]);
+        input.extend([0, 0, 0, 0, 0, 0, 0, 3, 0xff, 0b00000000, 0b01100000]);
+        input.push(0);
+        let mut output = Vec::new();
+
+        decompress_data(&mut Cursor::new(input), &mut output).unwrap();
+
+        assert_eq!(output, vec![0xff, 0x00, 0xff]);
+    }
+
+    // the file header BYTES_BLOCK goes with
+    fn bytes_file_header() -> FileHeader {
+        let mode = Mode {
+            alphabet: Alphabet::Bytes,
+            header: HeaderFormat::Tree,
+        };
+        FileHeader::new(mode, None, false)
+    }
+
+    #[test]
+    fn test_read_block_info() {
+        let mut input = BYTES_BLOCK.to_vec();
+        input.extend([2, 0, 0, 0, 0, 0, 0, 0, 2, b'h', b'i']);
+        input.push(0);
+        let mut input = input.as_slice();
+
+        assert_eq!(
+            read_block_info(&mut input, &bytes_file_header()).unwrap(),
+            Some(BlockInfo {
+                block_type: BlockType::Huffman,
+                compressed_size: BYTES_BLOCK.len() as u64,
+                original_size: 3,
+            })
+        );
+        assert_eq!(
+            read_block_info(&mut input, &bytes_file_header()).unwrap(),
+            Some(BlockInfo {
+                block_type: BlockType::Stored,
+                compressed_size: 11,
+                original_size: 2,
+            })
+        );
+        assert_eq!(
+            read_block_info(&mut input, &bytes_file_header()).unwrap(),
+            None
+        );
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_read_block_info_truncated() {
+        let mut input = &BYTES_BLOCK[..BYTES_BLOCK.len() - 1];
+
+        assert!(matches!(
+            read_block_info(&mut input, &bytes_file_header()),
+            Err(HuffError::TruncatedData)
+        ));
+    }
+
+    #[test]
+    fn test_decompress_invalid_block_type() {
+        let mut input = Cursor::new(huff_file(vec![1, 7]));
+
+        let result = decompress_data(&mut input, Vec::new());
+
+        assert!(matches!(result, Err(HuffError::InvalidBlockType(7))));
+    }
+
+    #[test]
+    fn test_file_header_round_trip() {
+        for original_length in [None, Some(0), Some(u64::MAX)] {
+            let header = FileHeader::new(Mode::default(), original_length, true);
+            let mut written = Vec::new();
+
+            write_file_header(&mut written, &header).unwrap();
+
+            assert_eq!(read_file_header(&mut written.as_slice()).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn test_file_header_shared_tree() {
+        let mut header = FileHeader::new(Mode::default(), Some(5), false);
+        header.shared_tree = Some(0xdeadbeef);
+        let mut written = Vec::new();
+
+        write_file_header(&mut written, &header).unwrap();
+
+        assert_eq!(read_file_header(&mut written.as_slice()).unwrap(), header);
+        assert!(matches!(
+            read_file_header(&mut &written[..written.len() - 1]),
+            Err(HuffError::TruncatedHeader)
+        ));
+    }
+
+    #[test]
+    fn test_decompress_not_huff() {
+        for input in [&b"PK\x03\x04"[..], b"HUF", b"huff\x01\x01\x00"] {
+            let result = decompress_data(&mut Cursor::new(input), Vec::new());
+
+            assert!(matches!(result, Err(HuffError::NotHuff)));
+        }
+    }
+
+    #[test]
+    fn test_decompress_unsupported_version() {
+        for version in [0, FORMAT_VERSION + 1] {
+            let mut input = Cursor::new(vec![b'H', b'U', b'F', b'F', version, 1, 0]);
+
+            let result = decompress_data(&mut input, Vec::new());
+
+            assert!(matches!(result, Err(HuffError::UnsupportedVersion(v)) if v == version));
+        }
+    }
+
+    // BYTES_BLOCK the way version 1 had it: no counts, and a byte after the
+    // data saying 3 bits of the last byte are used
+    const V1_BYTES_BLOCK: [u8; 18] = [
+        1, 0, 0, 0, 3, 0b01000000, 0b00111111, 0b11100000, 0, 0, 0, 0, 0, 0, 0, 2, 0b10100000, 3,
+    ];
+
+    fn v1_file(blocks: &[u8]) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        file.extend([1, 1]);
+        file.extend(blocks);
+        file.push(0);
+        file
+    }
+
+    #[test]
+    fn test_decompress_v1() {
+        let input = v1_file(&[V1_BYTES_BLOCK, V1_BYTES_BLOCK].concat());
+        let mut output = Vec::new();
+
+        decompress_data(&mut input.as_slice(), &mut output).unwrap();
+
+        assert_eq!(output, vec![0xff, 0x00, 0xff, 0xff, 0x00, 0xff]);
+
+        let mut input = input.as_slice();
+        let header = read_file_header(&mut input).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(
+            read_block_info(&mut input, &header).unwrap(),
+            Some(BlockInfo {
+                block_type: BlockType::Huffman,
+                compressed_size: V1_BYTES_BLOCK.len() as u64,
+                original_size: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_decompress_v1_bad_trailing_byte() {
+        let mut block = V1_BYTES_BLOCK;
+        block[17] = 9;
+
+        let result = decompress_data(&mut v1_file(&block).as_slice(), Vec::new());
+
+        assert!(matches!(result, Err(HuffError::TrailingByteMismatch(9))));
+    }
+
+    #[test]
+    fn test_decompress_newer_than_version() {
+        // reuse blocks came in with version 4
+        let mut input = MAGIC.to_vec();
+        input.extend([3, 1]);
+        input.extend(BYTES_BLOCK);
+        input.extend([3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        input.extend([0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let result = decompress_data(&mut input.as_slice(), Vec::new());
+
+        assert!(matches!(result, Err(HuffError::InvalidBlockType(3))));
+
+        // and the shared tree flag with version 6
+        let mut input = MAGIC.to_vec();
+        input.extend([5, SHARED_TREE_BIT | 1, 0, 0, 0, 0, 0]);
+
+        let result = decompress_data(&mut input.as_slice(), Vec::new());
+
+        assert!(matches!(result, Err(HuffError::InvalidMode(_))));
+    }
+
+    #[test]
+    fn test_decompress_truncated_file_header() {
+        let mut input = Cursor::new(huff_file(vec![LENGTH_BIT | 1, 0, 0]));
+
+        let result = decompress_data(&mut input, Vec::new());
+
+        assert!(matches!(result, Err(HuffError::TruncatedHeader)));
+    }
+
+    #[test]
+    fn test_decompress_length_mismatch() {
+        let mut input = huff_file(vec![LENGTH_BIT | 1, 0, 0, 0, 0, 0, 0, 0, 4]);
+        input.extend(BYTES_BLOCK);
+        input.push(0);
+
+        let result = decompress_data(&mut Cursor::new(input), Vec::new());
+
+        assert!(matches!(
+            result,
+            Err(HuffError::LengthMismatch {
+                expected: 4,
+                actual: 3
+            })
+        ));
+    }
 }
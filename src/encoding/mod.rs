@@ -1,8 +1,15 @@
+pub mod adaptive;
 pub mod bitreader;
 pub mod bitwriter;
+pub mod canonical;
+pub mod crc32;
 pub mod decoding;
+#[allow(clippy::module_inception)]
 pub mod encoding;
 pub mod frequency;
 pub mod huffio;
+pub mod package_merge;
+pub mod symbol;
+pub mod table_decoder;
 pub mod test_cases;
 pub mod tree;
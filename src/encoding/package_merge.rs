@@ -0,0 +1,172 @@
+use crate::error::HuffError;
+
+/// An item in one of the package-merge lists: either one of the original
+/// symbols, or a package of two items from the list one level down.
+#[derive(Debug, Clone, Copy)]
+enum Item {
+    Leaf(usize),
+    Package(usize, usize),
+}
+
+/// Finds optimal code lengths that are all at most `max_code_length`, using
+/// the package-merge algorithm.
+///
+/// `weights[i]` is the weight of symbol i, and the returned vec has the code
+/// length for each symbol in the same order.
+///
+/// the idea: every symbol starts out as a coin worth 2^-l at each level l from
+/// 1 to max_code_length. starting at the deepest level, pair up the cheapest
+/// coins into packages and merge them into the level above. picking the 2n - 2
+/// cheapest items at the top and counting how many times each symbol got used
+/// gives its code length.
+pub fn code_lengths(weights: &[u64], max_code_length: u8) -> Result<Vec<u8>, HuffError> {
+    let n = weights.len();
+
+    if max_code_length == 0 || max_code_length > 32 {
+        return Err(HuffError::MaxCodeLengthTooSmall(max_code_length));
+    }
+    match n {
+        0 => return Ok(Vec::new()),
+        // a lone symbol still needs one bit
+        1 => return Ok(vec![1]),
+        _ => {}
+    }
+    if (n as u64) > 1u64 << max_code_length {
+        return Err(HuffError::MaxCodeLengthTooSmall(max_code_length));
+    }
+
+    let mut leaves: Vec<usize> = (0..n).collect();
+    leaves.sort_by_key(|&i| (weights[i], i));
+
+    // levels[0] is the deepest level, which only holds the leaves
+    let mut levels: Vec<Vec<(u64, Item)>> = Vec::with_capacity(max_code_length as usize);
+    levels.push(
+        leaves
+            .iter()
+            .map(|&i| (weights[i], Item::Leaf(i)))
+            .collect(),
+    );
+
+    for _ in 1..max_code_length {
+        let prev = levels.last().unwrap();
+        let packages = prev
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(j, pair)| (pair[0].0 + pair[1].0, Item::Package(2 * j, 2 * j + 1)));
+
+        // merge the packages in with a fresh copy of the leaves, keeping it sorted
+        let mut merged = Vec::with_capacity(n + prev.len() / 2);
+        let mut leaves = leaves
+            .iter()
+            .map(|&i| (weights[i], Item::Leaf(i)))
+            .peekable();
+        let mut packages = packages.peekable();
+        loop {
+            let take_leaf = match (leaves.peek(), packages.peek()) {
+                (Some(leaf), Some(package)) => leaf.0 <= package.0,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            if take_leaf {
+                merged.push(leaves.next().unwrap());
+            } else {
+                merged.push(packages.next().unwrap());
+            }
+        }
+
+        levels.push(merged);
+    }
+
+    // walk back down from the top level, expanding every chosen package into
+    // its two halves and counting every leaf we hit along the way
+    let mut lengths = vec![0u8; n];
+    let mut chosen: Vec<bool> = vec![true; 2 * n - 2];
+    for level in levels.iter().rev() {
+        let mut chosen_below = vec![false; level.len()];
+        let mut below_len = 0;
+
+        for (item, _) in level.iter().zip(&chosen).filter(|(_, &chosen)| chosen) {
+            match item.1 {
+                Item::Leaf(i) => lengths[i] += 1,
+                Item::Package(a, b) => {
+                    chosen_below[a] = true;
+                    chosen_below[b] = true;
+                    below_len = below_len.max(b + 1);
+                }
+            }
+        }
+
+        chosen_below.truncate(below_len);
+        chosen = chosen_below;
+    }
+
+    Ok(lengths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kraft_sum(lengths: &[u8]) -> f64 {
+        lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum()
+    }
+
+    #[test]
+    fn test_matches_huffman_when_unconstrained() {
+        // plain huffman gives 1, 2, 3, 3 here
+        let weights = [10, 5, 2, 1];
+
+        let lengths = code_lengths(&weights, 32).unwrap();
+
+        assert_eq!(lengths, vec![1, 2, 3, 3]);
+    }
+
+    #[test]
+    fn test_limits_length() {
+        let weights = [1, 1, 2, 4, 8, 16, 32, 64];
+
+        let lengths = code_lengths(&weights, 4).unwrap();
+
+        assert!(lengths.iter().all(|&l| l <= 4));
+        assert_eq!(kraft_sum(&lengths), 1.0);
+        // heavier symbols never get longer codes than lighter ones
+        for pair in lengths.windows(2) {
+            assert!(pair[0] >= pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_fibonacci_weights() {
+        let mut weights = vec![1u64, 1];
+        while weights.len() < 40 {
+            weights.push(weights[weights.len() - 1] + weights[weights.len() - 2]);
+        }
+
+        for max_code_length in [12, 15, 24, 32] {
+            let lengths = code_lengths(&weights, max_code_length).unwrap();
+
+            assert_eq!(lengths.iter().max(), Some(&max_code_length));
+            assert_eq!(kraft_sum(&lengths), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_single_symbol() {
+        assert_eq!(code_lengths(&[7], 1).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_too_many_symbols() {
+        let result = code_lengths(&[1, 1, 1, 1, 1], 2);
+
+        assert!(matches!(result, Err(HuffError::MaxCodeLengthTooSmall(2))));
+    }
+
+    #[test]
+    fn test_exactly_full() {
+        let lengths = code_lengths(&[1, 1, 1, 1000], 2).unwrap();
+
+        assert_eq!(lengths, vec![2, 2, 2, 2]);
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use crate::error::HuffError;
+
+/// Which kind of symbol the input is split into before it gets encoded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    /// UTF-8 characters, with a 32 bit scalar value per leaf in the header.
+    /// Input that isn't valid UTF-8 is rejected.
+    Chars,
+    /// Raw bytes, with an 8 bit value per leaf in the header.
+    /// Works for any input.
+    #[default]
+    Bytes,
+}
+
+impl Alphabet {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Alphabet::Chars => 0,
+            Alphabet::Bytes => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self, HuffError> {
+        match byte {
+            0 => Ok(Alphabet::Chars),
+            1 => Ok(Alphabet::Bytes),
+            _ => Err(HuffError::InvalidMode(byte)),
+        }
+    }
+}
+
+/// Something that can be a leaf in a Huffman tree.
+pub trait Symbol: Copy + Eq + Hash + Ord + Debug {
+    /// How many bits a leaf takes up in the tree header.
+    const BITS: u8;
+
+    /// The alphabet made up of this kind of symbol.
+    const ALPHABET: Alphabet;
+
+    fn to_bits(self) -> u32;
+
+    fn from_bits(bits: u32) -> Result<Self, HuffError>;
+
+    /// Splits `chunk` into symbols and hands each one to `f`.
+    /// Bytes left over from the previous chunk are in `incomplete`, and anything
+    /// at the end of this chunk that isn't a whole symbol yet is put back there.
+    fn split<F: FnMut(Self) -> Result<(), HuffError>>(
+        incomplete: &mut Vec<u8>,
+        chunk: &[u8],
+        f: F,
+    ) -> Result<(), HuffError>;
+
+    /// Appends the raw bytes of this symbol to `out`.
+    fn write_to(self, out: &mut Vec<u8>);
+
+    /// How many raw bytes `write_to` appends.
+    fn byte_len(self) -> usize;
+
+    /// The symbol as something safe to print: control and whitespace
+    /// characters (space included) and backslashes are escaped, everything
+    /// else is shown as is.
+    fn escape(self) -> String;
+}
+
+/// The escapes `Symbol::escape` uses for both alphabets.
+fn escape_common(c: char) -> Option<&'static str> {
+    match c {
+        '\n' => Some("\\n"),
+        '\r' => Some("\\r"),
+        '\t' => Some("\\t"),
+        '\\' => Some("\\\\"),
+        _ => None,
+    }
+}
+
+impl Symbol for char {
+    const BITS: u8 = 32;
+    const ALPHABET: Alphabet = Alphabet::Chars;
+
+    fn to_bits(self) -> u32 {
+        self as u32
+    }
+
+    fn from_bits(bits: u32) -> Result<Self, HuffError> {
+        char::from_u32(bits).ok_or(HuffError::InvalidScalarValue(bits))
+    }
+
+    fn split<F: FnMut(Self) -> Result<(), HuffError>>(
+        incomplete: &mut Vec<u8>,
+        chunk: &[u8],
+        mut f: F,
+    ) -> Result<(), HuffError> {
+        let mut data = std::mem::take(incomplete);
+        data.extend_from_slice(chunk);
+
+        let (valid, curr_incomplete) = match std::str::from_utf8(&data) {
+            Ok(valid_str) => (valid_str, &[] as &[u8]),
+            // error_len() is None when the input just ends partway through a char,
+            // which is fine since the rest of it may be in the next chunk
+            Err(e) if e.error_len().is_none() => {
+                let valid_up_to = e.valid_up_to();
+                (
+                    std::str::from_utf8(&data[..valid_up_to]).unwrap(),
+                    &data[valid_up_to..],
+                )
+            }
+            Err(_) => return Err(HuffError::InvalidUtf8),
+        };
+
+        for ch in valid.chars() {
+            f(ch)?;
+        }
+
+        *incomplete = curr_incomplete.to_vec();
+        Ok(())
+    }
+
+    fn write_to(self, out: &mut Vec<u8>) {
+        let mut buf = [0u8; 4];
+        out.extend_from_slice(self.encode_utf8(&mut buf).as_bytes());
+    }
+
+    fn byte_len(self) -> usize {
+        self.len_utf8()
+    }
+
+    fn escape(self) -> String {
+        match escape_common(self) {
+            Some(escaped) => escaped.to_string(),
+            None if self.is_control() || self.is_whitespace() => {
+                format!("\\u{{{:x}}}", self as u32)
+            }
+            None => self.to_string(),
+        }
+    }
+}
+
+impl Symbol for u8 {
+    const BITS: u8 = 8;
+    const ALPHABET: Alphabet = Alphabet::Bytes;
+
+    fn to_bits(self) -> u32 {
+        self as u32
+    }
+
+    fn from_bits(bits: u32) -> Result<Self, HuffError> {
+        u8::try_from(bits).map_err(|_| HuffError::InvalidScalarValue(bits))
+    }
+
+    fn split<F: FnMut(Self) -> Result<(), HuffError>>(
+        _incomplete: &mut Vec<u8>,
+        chunk: &[u8],
+        mut f: F,
+    ) -> Result<(), HuffError> {
+        for &byte in chunk {
+            f(byte)?;
+        }
+        Ok(())
+    }
+
+    fn write_to(self, out: &mut Vec<u8>) {
+        out.push(self);
+    }
+
+    fn byte_len(self) -> usize {
+        1
+    }
+
+    fn escape(self) -> String {
+        match escape_common(self as char) {
+            Some(escaped) => escaped.to_string(),
+            None if self.is_ascii_graphic() => (self as char).to_string(),
+            None => format!("\\x{:02x}", self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_all<S: Symbol>(chunks: &[&[u8]]) -> Result<(Vec<S>, Vec<u8>), HuffError> {
+        let mut incomplete = Vec::new();
+        let mut symbols = Vec::new();
+        for chunk in chunks {
+            S::split(&mut incomplete, chunk, |s| {
+                symbols.push(s);
+                Ok(())
+            })?;
+        }
+        Ok((symbols, incomplete))
+    }
+
+    #[test]
+    fn test_split_chars_across_chunks() {
+        let input = "пр".as_bytes();
+
+        let (symbols, incomplete) = split_all::<char>(&[&input[..1], &input[1..3]]).unwrap();
+
+        assert_eq!(symbols, vec!['п']);
+        assert_eq!(incomplete, vec![input[2]]);
+    }
+
+    #[test]
+    fn test_split_invalid_utf8() {
+        let result = split_all::<char>(&[&[b'a', 0xff, b'b']]);
+
+        assert!(matches!(result, Err(HuffError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn test_split_bytes() {
+        let (symbols, incomplete) = split_all::<u8>(&[&[b'a', 0xff], &[0x00]]).unwrap();
+
+        assert_eq!(symbols, vec![b'a', 0xff, 0x00]);
+        assert!(incomplete.is_empty());
+    }
+
+    #[test]
+    fn test_write_to() {
+        let mut out = Vec::new();
+
+        'é'.write_to(&mut out);
+        0xffu8.write_to(&mut out);
+
+        assert_eq!(out, vec![0xc3, 0xa9, 0xff]);
+        assert_eq!('é'.byte_len() + 0xffu8.byte_len(), out.len());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!('a'.escape(), "a");
+        assert_eq!('é'.escape(), "é");
+        assert_eq!('\n'.escape(), "\\n");
+        assert_eq!(' '.escape(), "\\u{20}");
+        assert_eq!('\u{a0}'.escape(), "\\u{a0}");
+        assert_eq!('\\'.escape(), "\\\\");
+
+        assert_eq!(b'a'.escape(), "a");
+        assert_eq!(b'\t'.escape(), "\\t");
+        assert_eq!(b' '.escape(), "\\x20");
+        assert_eq!(0xffu8.escape(), "\\xff");
+    }
+
+    #[test]
+    fn test_alphabet_round_trip() {
+        for alphabet in [Alphabet::Chars, Alphabet::Bytes] {
+            assert_eq!(Alphabet::from_byte(alphabet.to_byte()).unwrap(), alphabet);
+        }
+        assert!(matches!(
+            Alphabet::from_byte(7),
+            Err(HuffError::InvalidMode(7))
+        ));
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+
+use super::bitreader::BitReader;
+use super::symbol::Symbol;
+use crate::error::HuffError;
+
+/// How many bits the primary table looks at in one go.
+pub const PRIMARY_BITS: u8 = 11;
+
+/// The most bits a secondary table looks at in one go. Codes that are longer
+/// still go on to another secondary table under it, so no table ever has more
+/// than 2^`SECONDARY_BITS` entries, however long the codes are.
+pub const SECONDARY_BITS: u8 = 8;
+
+#[derive(Debug, Clone, Copy)]
+enum Entry<S> {
+    /// No code starts with these bits.
+    Empty,
+    /// A code of `length` bits for `symbol` starts with these bits.
+    Symbol { symbol: S, length: u8 },
+    /// Codes starting with these bits are longer than this table, so look up
+    /// the next `bits` bits in the secondary table starting at `offset`.
+    Secondary { offset: usize, bits: u8 },
+}
+
+/// Lookup tables for decoding a whole code at once instead of bit by bit.
+///
+/// The primary table is indexed by the next [`PRIMARY_BITS`] bits of input. For
+/// a code that's shorter than that, every index that starts with the code points
+/// at its symbol, so whatever bits come after it don't matter. Codes that are
+/// longer are grouped by their first [`PRIMARY_BITS`] bits, and each group gets
+/// its own secondary table, indexed by the bits after those. Secondary tables
+/// work the same way, so a group can have groups of its own.
+///
+/// Like zlib's inflate, a secondary table only gets as many bits as the codes
+/// under it fill up, and never more than [`SECONDARY_BITS`].
+#[derive(Debug)]
+pub struct DecodeTable<S> {
+    primary: Vec<Entry<S>>,
+    secondary: Vec<Entry<S>>,
+}
+
+/// A code, as (prefix, length, symbol).
+type Code<S> = (u32, u8, S);
+
+impl<S: Symbol> DecodeTable<S> {
+    pub fn new(prefix_table: &HashMap<S, (u32, u8)>) -> Result<Self, HuffError> {
+        let mut codes: Vec<Code<S>> = prefix_table
+            .iter()
+            .map(|(&symbol, &(prefix, length))| (prefix, length, symbol))
+            .collect();
+        // in the order of their bits, so the codes starting with the same bits
+        // are always next to each other
+        codes.sort_by_key(|&(prefix, length, _)| ((prefix as u64) << (32 - length), length));
+
+        let mut table = DecodeTable {
+            primary: vec![Entry::Empty; 1 << PRIMARY_BITS],
+            secondary: Vec::new(),
+        };
+        let mut primary = std::mem::take(&mut table.primary);
+        table.fill_table(&mut primary, &codes, 0, PRIMARY_BITS)?;
+        table.primary = primary;
+
+        Ok(table)
+    }
+
+    /// Fills in `slots`, the table for `codes`, which all start with the same
+    /// `skipped` bits and are indexed by the `bits` after those. Codes that
+    /// don't end within the table get secondary tables of their own.
+    fn fill_table(
+        &mut self,
+        slots: &mut [Entry<S>],
+        codes: &[Code<S>],
+        skipped: u8,
+        bits: u8,
+    ) -> Result<(), HuffError> {
+        let depth = skipped + bits;
+        let mut rest = codes;
+
+        while let Some(&(prefix, length, symbol)) = rest.first() {
+            if length <= depth {
+                let code = (prefix & mask(length - skipped)) as usize;
+                let start = code << (depth - length);
+                let end = (code + 1) << (depth - length);
+                fill(
+                    &mut slots[start..end],
+                    Entry::Symbol { symbol, length },
+                    prefix,
+                    length,
+                )?;
+                rest = &rest[1..];
+                continue;
+            }
+
+            // everything that shares this code's first `depth` bits
+            let index = (prefix >> (length - depth)) & mask(bits);
+            let group = rest
+                .iter()
+                .take_while(|&&(prefix, length, _)| {
+                    length > depth && (prefix >> (length - depth)) & mask(bits) == index
+                })
+                .count();
+            let (group, next) = rest.split_at(group);
+            rest = next;
+
+            let sub_bits = secondary_bits(group, depth);
+            let offset = self.secondary.len();
+            let entry = Entry::Secondary {
+                offset,
+                bits: sub_bits,
+            };
+            fill(
+                &mut slots[index as usize..index as usize + 1],
+                entry,
+                prefix,
+                length,
+            )?;
+
+            let mut sub_table = vec![Entry::Empty; 1 << sub_bits];
+            self.secondary.extend_from_slice(&sub_table);
+            self.fill_table(&mut sub_table, group, depth, sub_bits)?;
+            self.secondary[offset..offset + sub_table.len()].copy_from_slice(&sub_table);
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the next symbol and moves past its code.
+    /// `available` is how many bits are left in the encoded data, which is
+    /// needed because peeking pads the end with zeros.
+    pub fn decode_symbol(&self, br: &mut BitReader, available: usize) -> Result<S, HuffError> {
+        let mut entry = self.primary[br.peek_value(PRIMARY_BITS) as usize];
+        let mut skipped = PRIMARY_BITS;
+
+        while let Entry::Secondary { offset, bits } = entry {
+            let index = br.peek_value(skipped + bits) & mask(bits);
+            entry = self.secondary[offset + index as usize];
+            skipped += bits;
+        }
+
+        match entry {
+            Entry::Symbol { symbol, length } if length as usize <= available => {
+                br.consume(length);
+                Ok(symbol)
+            }
+            // the code runs past the end of the data
+            Entry::Symbol { .. } => Err(HuffError::TruncatedData),
+            _ => Err(HuffError::UnknownPrefix),
+        }
+    }
+}
+
+/// How many bits the secondary table for `codes` gets, which all go past the
+/// first `depth` bits. Starting from the shortest code, it grows a bit at a
+/// time for as long as the codes that end within it leave slots unfilled, the
+/// way zlib sizes its tables.
+fn secondary_bits<S>(codes: &[Code<S>], depth: u8) -> u8 {
+    let mut counts = [0u64; 33];
+    for &(_, length, _) in codes {
+        counts[(length - depth) as usize] += 1;
+    }
+    let longest = codes.iter().map(|&(_, length, _)| length - depth).max();
+    let longest = longest.unwrap_or(1).min(SECONDARY_BITS);
+
+    let mut bits = codes
+        .iter()
+        .map(|&(_, length, _)| length - depth)
+        .min()
+        .unwrap_or(1)
+        .min(longest);
+    // slots a table of `bits` bits has left over after the codes that end in it
+    let mut left = (1u64 << bits).saturating_sub(counts[bits as usize]);
+    while left > 0 && bits < longest {
+        bits += 1;
+        left = (left << 1).saturating_sub(counts[bits as usize]);
+    }
+    bits
+}
+
+/// The low `bits` bits set.
+fn mask(bits: u8) -> u32 {
+    ((1u64 << bits) - 1) as u32
+}
+
+fn fill<S: Copy>(
+    slots: &mut [Entry<S>],
+    entry: Entry<S>,
+    prefix: u32,
+    length: u8,
+) -> Result<(), HuffError> {
+    for slot in slots {
+        if !matches!(slot, Entry::Empty) {
+            // some other code already claimed this slot, so one is a prefix of the other
+            return Err(HuffError::DuplicatePrefix { prefix, length });
+        }
+        *slot = entry;
+    }
+    Ok(())
+}
+
+/// Same as `decode_data`, but resolves each code with a [`DecodeTable`] lookup.
+pub fn decode_data_with_table<S: Symbol>(
+    data: &[u8],
+    prefix_table: HashMap<S, (u32, u8)>,
+    symbol_count: u64,
+) -> Result<Vec<S>, HuffError> {
+    // every code is at least a bit long
+    if symbol_count > data.len() as u64 * 8 {
+        return Err(HuffError::TruncatedData);
+    }
+    let total_bits = data.len() * 8;
+
+    let table = DecodeTable::new(&prefix_table)?;
+    let mut br = BitReader::new(data.to_vec());
+    let mut characters: Vec<S> = Vec::with_capacity(symbol_count as usize);
+
+    for _ in 0..symbol_count {
+        let available = total_bits - br.bits_read();
+        characters.push(table.decode_symbol(&mut br, available)?);
+    }
+
+    Ok(characters)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::encoding::canonical::generate_canonical_prefix_table;
+    use crate::encoding::decoding::decode_data;
+    use crate::encoding::encoding::{generate_prefix_table, get_encoded_data};
+    use crate::encoding::frequency::Freq;
+    use crate::encoding::test_cases;
+    use crate::encoding::tree::{generate_tree, generate_tree_with_max_length};
+
+    use super::*;
+
+    fn round_trip<S: Symbol>(freq: &Freq<S>, input: &[u8], max_code_length: u8) -> Vec<S> {
+        let root = generate_tree_with_max_length(freq, max_code_length).unwrap();
+        let prefix_table = generate_prefix_table(root).unwrap();
+        let encoded = get_encoded_data(Cursor::new(input), prefix_table.clone()).unwrap();
+
+        let expected =
+            decode_data(&encoded.data, prefix_table.clone(), encoded.symbol_count).unwrap();
+        let result =
+            decode_data_with_table(&encoded.data, prefix_table, encoded.symbol_count).unwrap();
+
+        assert_eq!(result, expected);
+        result
+    }
+
+    #[test]
+    fn test_table_decode_sample() {
+        let input = test_cases::SAMPLE_TEST.as_bytes();
+        let mut freq: Freq = Freq::new();
+        freq.update(input).unwrap();
+
+        let result = round_trip(&freq, input, 32);
+
+        assert_eq!(
+            result,
+            test_cases::SAMPLE_TEST.chars().collect::<Vec<char>>()
+        );
+    }
+
+    #[test]
+    fn test_table_decode_one_distinct_char() {
+        let mut freq: Freq = Freq::new();
+        freq.update("aaa".as_bytes()).unwrap();
+
+        let result = round_trip(&freq, "aaa".as_bytes(), 32);
+
+        assert_eq!(result, vec!['a', 'a', 'a']);
+    }
+
+    #[test]
+    fn test_table_decode_long_codes() {
+        // fibonacci counts make codes up to 19 bits long, so plenty of them
+        // go through the secondary tables
+        let mut input = Vec::new();
+        let (mut a, mut b) = (1, 1);
+        for symbol in 0..20u8 {
+            input.extend(std::iter::repeat_n(symbol, a));
+            (a, b) = (b, a + b);
+        }
+        let mut freq: Freq<u8> = Freq::new();
+        freq.update(&input).unwrap();
+        assert_eq!(
+            generate_prefix_table(generate_tree(&freq))
+                .unwrap()
+                .values()
+                .map(|&(_, length)| length)
+                .max(),
+            Some(19)
+        );
+
+        for max_code_length in [12, 15, 32] {
+            let result = round_trip(&freq, &input, max_code_length);
+
+            assert_eq!(result, input);
+        }
+    }
+
+    #[test]
+    fn test_table_decode_32_bit_code() {
+        // lengths 1 to 32, plus a second 32 bit code to finish the tree off
+        let mut code_lengths: HashMap<u8, u8> = (1..=32).map(|length| (length, length)).collect();
+        code_lengths.insert(33, 32);
+        let prefix_table = generate_canonical_prefix_table(&code_lengths).unwrap();
+        let input: Vec<u8> = (1..=33).chain((1..=33).rev()).collect();
+        let encoded = get_encoded_data(Cursor::new(&input), prefix_table.clone()).unwrap();
+
+        let table = DecodeTable::new(&prefix_table).unwrap();
+        let result =
+            decode_data_with_table(&encoded.data, prefix_table, encoded.symbol_count).unwrap();
+
+        assert_eq!(result, input);
+        // three levels of secondary tables under the all ones prefix, the last
+        // one only as big as the 5 bits left
+        assert_eq!(table.secondary.len(), 256 + 256 + 32);
+    }
+
+    #[test]
+    fn test_table_size_for_lone_long_code() {
+        // a 32 bit code alone under its prefix doesn't get a 2^21 entry table
+        let prefix_table = HashMap::from([('a', (0b0, 1)), ('b', (1 << 31, 32))]);
+
+        let table = DecodeTable::new(&prefix_table).unwrap();
+
+        assert_eq!(table.secondary.len(), 256 + 256 + 32);
+    }
+
+    #[test]
+    fn test_table_rejects_overlapping_codes() {
+        let prefix_table = HashMap::from([('a', (0b0, 1)), ('b', (0b01, 2))]);
+
+        let result = DecodeTable::new(&prefix_table);
+
+        assert!(matches!(result, Err(HuffError::DuplicatePrefix { .. })));
+
+        // the same, but in the secondary tables
+        for prefix_table in [
+            HashMap::from([('a', (0x7ff, 11)), ('b', (0xffe, 12))]),
+            HashMap::from([('a', (0xfff, 12)), ('b', (0x1ffe0, 17))]),
+            HashMap::from([('a', (0xfffff, 20)), ('b', (0x1fffff, 21))]),
+        ] {
+            let result = DecodeTable::new(&prefix_table);
+
+            assert!(matches!(result, Err(HuffError::DuplicatePrefix { .. })));
+        }
+    }
+
+    #[test]
+    fn test_table_decode_unknown_prefix() {
+        // 'a' is 0 and 'b' is 10, so nothing starts with 11
+        let prefix_table = HashMap::from([('a', (0b0, 1)), ('b', (0b10, 2))]);
+        let input = vec![0b01100000];
+
+        let result = decode_data_with_table(&input, prefix_table, 2);
+
+        assert!(matches!(result, Err(HuffError::UnknownPrefix)));
+    }
+
+    #[test]
+    fn test_table_decode_runs_out() {
+        // the last 'b' would need a bit past the end of the data
+        let prefix_table = HashMap::from([('a', (0b0, 1)), ('b', (0b10, 2))]);
+        let input = vec![0b00000001];
+
+        let result = decode_data_with_table(&input, prefix_table, 8);
+
+        assert!(matches!(result, Err(HuffError::TruncatedData)));
+    }
+}
@@ -1,42 +1,65 @@
+use super::bitreader::BitReader;
+use super::bitwriter::BitWriter;
+use super::canonical::generate_canonical_prefix_table;
 use super::frequency::Freq;
-use std::collections::BinaryHeap;
+use super::package_merge;
+use super::symbol::Symbol;
+use crate::error::HuffError;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
 
-pub fn generate_tree(freq: &Freq) -> Option<Box<HuffNode>> {
-    let mut min_heap = BinaryHeap::new();
-
-    for (character, weight) in freq.counter.iter() {
-        let curr_node = Box::new(HuffNode::new(Some(*character), *weight));
-        // let curr_node = Rc::new(RefCell::new(HuffNode::new(Some(character), weight)));
-        min_heap.push(curr_node);
-    }
+pub fn generate_tree<S: Symbol>(freq: &Freq<S>) -> Option<Box<HuffNode<S>>> {
+    HuffmanTree::from_freq(freq).to_boxed()
+}
 
-    while min_heap.len() > 1 {
-        let first = min_heap.pop().unwrap();
-        let second = min_heap.pop().unwrap();
+/// Builds a tree whose codes are at most `max_code_length` bits long.
+/// If the plain Huffman tree already fits it's used as-is, since it's optimal;
+/// otherwise the code lengths come from package-merge instead.
+pub fn generate_tree_with_max_length<S: Symbol>(
+    freq: &Freq<S>,
+    max_code_length: u8,
+) -> Result<Option<Box<HuffNode<S>>>, HuffError> {
+    Ok(HuffmanTree::with_max_length(freq, max_code_length)?.to_boxed())
+}
 
-        let mut new_node = Box::new(HuffNode::new(None, first.weight + second.weight));
+/// Builds the optimal tree with no code longer than `max_code_length` bits, using
+/// package-merge to pick code lengths and then laying the leaves out canonically.
+pub fn generate_length_limited_tree<S: Symbol>(
+    freq: &Freq<S>,
+    max_code_length: u8,
+) -> Result<Option<Box<HuffNode<S>>>, HuffError> {
+    Ok(HuffmanTree::length_limited(freq, max_code_length)?.to_boxed())
+}
 
-        new_node.left = Some(first);
-        new_node.right = Some(second);
+/// Rebuilds a tree from code lengths by giving each symbol its canonical code and
+/// following that path down from the root. Leaf weights come from `freq`, and
+/// internal nodes get the sum of their children.
+pub fn tree_from_code_lengths<S: Symbol>(
+    code_lengths: &HashMap<S, u8>,
+    freq: &Freq<S>,
+) -> Result<Option<Box<HuffNode<S>>>, HuffError> {
+    Ok(HuffmanTree::from_code_lengths(code_lengths, freq)?.to_boxed())
+}
 
-        min_heap.push(new_node);
-    }
+/// Number of edges between the root and the deepest leaf.
+pub fn tree_depth<S: Symbol>(node: &Option<Box<HuffNode<S>>>) -> usize {
+    HuffmanTree::from_boxed(node).depth()
+}
 
-    min_heap.pop()
+/// See `HuffmanTree::to_dot`.
+pub fn tree_to_dot<S: Symbol>(node: &Option<Box<HuffNode<S>>>) -> String {
+    HuffmanTree::from_boxed(node).to_dot()
 }
 
 #[allow(dead_code)]
-pub fn print_tree(node: &Option<Box<HuffNode>>, prefix: &str, is_left: bool) {
+pub fn print_tree<S: Symbol>(node: &Option<Box<HuffNode<S>>>, prefix: &str, is_left: bool) {
     if let Some(node) = node {
         println!(
-            "{}{} - char: {}",
+            "{}{} - char: {:?}",
             prefix,
             if is_left { "├──" } else { "└──" },
-            if node.character != None {
-                node.character.unwrap()
-            } else {
-                '9'
-            }
+            node.character
         );
 
         let new_prefix = format!("{}{}", prefix, if is_left { "│   " } else { "    " });
@@ -45,23 +68,532 @@ pub fn print_tree(node: &Option<Box<HuffNode>>, prefix: &str, is_left: bool) {
     }
 }
 
-#[derive(Debug)]
-pub struct HuffNode {
-    pub character: Option<char>,
-    pub weight: u32,
-    pub left: Option<Box<HuffNode>>,
-    pub right: Option<Box<HuffNode>>,
+/// A node in a `HuffmanTree`. Children are indices into the tree's `nodes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeNode<S = char> {
+    pub character: Option<S>,
+    pub weight: u64,
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+}
+
+impl<S> TreeNode<S> {
+    pub fn new(character: Option<S>, weight: u64) -> Self {
+        Self {
+            character,
+            weight,
+            left: None,
+            right: None,
+        }
+    }
 }
 
-/* make this cache friendly later
-pub struct HuffmanTree {
-    nodes: Vec<HuffNode>,
-    pub root: usize
+/// Huffman tree kept in one Vec, with nodes pointing at each other by index.
+/// Everything here walks it with an explicit stack instead of recursing, so a
+/// degenerate tree (say, from a corrupt header) can't overflow the call stack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HuffmanTree<S = char> {
+    nodes: Vec<TreeNode<S>>,
+    root: Option<usize>,
 }
-*/
 
-impl HuffNode {
-    pub fn new(character: Option<char>, weight: u32) -> Self {
+impl<S> Default for HuffmanTree<S> {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            root: None,
+        }
+    }
+}
+
+impl<S: Symbol> HuffmanTree<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn root(&self) -> Option<usize> {
+        self.root
+    }
+
+    pub fn node(&self, index: usize) -> &TreeNode<S> {
+        &self.nodes[index]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn push(&mut self, node: TreeNode<S>) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Plain Huffman construction. Ties are broken the same way as `HuffNode`'s
+    /// `Ord`, so this builds the same shape `generate_tree` always has.
+    pub fn from_freq(freq: &Freq<S>) -> Self {
+        let freq = scale_weights(freq);
+        let mut tree = Self::new();
+        tree.nodes.reserve(freq.counter.len() * 2);
+        let mut min_heap = BinaryHeap::with_capacity(freq.counter.len());
+
+        for (character, weight) in freq.counter.iter() {
+            let index = tree.push(TreeNode::new(Some(*character), *weight));
+            min_heap.push(HeapEntry {
+                weight: *weight,
+                character: Some(*character),
+                index,
+            });
+        }
+
+        while min_heap.len() > 1 {
+            let first = min_heap.pop().unwrap();
+            let second = min_heap.pop().unwrap();
+
+            let weight = first.weight + second.weight;
+            let index = tree.push(TreeNode {
+                character: None,
+                weight,
+                left: Some(first.index),
+                right: Some(second.index),
+            });
+
+            min_heap.push(HeapEntry {
+                weight,
+                character: None,
+                index,
+            });
+        }
+
+        tree.root = min_heap.pop().map(|entry| entry.index);
+        tree
+    }
+
+    /// See `generate_tree_with_max_length`.
+    pub fn with_max_length(freq: &Freq<S>, max_code_length: u8) -> Result<Self, HuffError> {
+        let tree = Self::from_freq(freq);
+
+        // a lone leaf at the root still gets a one bit code
+        if tree.depth().max(1) <= max_code_length as usize {
+            return Ok(tree);
+        }
+
+        Self::length_limited(freq, max_code_length)
+    }
+
+    /// See `generate_length_limited_tree`.
+    pub fn length_limited(freq: &Freq<S>, max_code_length: u8) -> Result<Self, HuffError> {
+        let freq = scale_weights(freq);
+        let mut symbols: Vec<(S, u64)> = freq.counter.iter().map(|(&s, &w)| (s, w)).collect();
+        symbols.sort();
+
+        let weights: Vec<u64> = symbols.iter().map(|&(_, w)| w).collect();
+        let lengths = package_merge::code_lengths(&weights, max_code_length)?;
+
+        let code_lengths: HashMap<S, u8> = symbols
+            .iter()
+            .zip(lengths)
+            .map(|(&(symbol, _), length)| (symbol, length))
+            .collect();
+
+        Self::from_code_lengths(&code_lengths, &freq)
+    }
+
+    /// See `tree_from_code_lengths`.
+    pub fn from_code_lengths(
+        code_lengths: &HashMap<S, u8>,
+        freq: &Freq<S>,
+    ) -> Result<Self, HuffError> {
+        let prefix_table = generate_canonical_prefix_table(code_lengths)?;
+        let freq = scale_weights(freq);
+        let weight_of = |symbol: &S| freq.counter.get(symbol).copied().unwrap_or(0);
+        let mut tree = Self::new();
+
+        // a single symbol is the root itself, like from_freq does it
+        if prefix_table.len() == 1 {
+            let (symbol, _) = prefix_table.iter().next().unwrap();
+            tree.root = Some(tree.push(TreeNode::new(Some(*symbol), weight_of(symbol))));
+            return Ok(tree);
+        }
+
+        if prefix_table.is_empty() {
+            return Ok(tree);
+        }
+
+        let root = tree.push(TreeNode::new(None, 0));
+        tree.root = Some(root);
+
+        for (symbol, &(prefix, length)) in prefix_table.iter() {
+            let weight = weight_of(symbol);
+            let mut node = root;
+            tree.nodes[node].weight += weight;
+
+            for i in (0..length).rev() {
+                let bit = (prefix >> i) & 1;
+                let child = match bit {
+                    0 => tree.nodes[node].left,
+                    _ => tree.nodes[node].right,
+                };
+                node = match child {
+                    Some(child) => child,
+                    None => {
+                        let child = tree.push(TreeNode::new(None, 0));
+                        match bit {
+                            0 => tree.nodes[node].left = Some(child),
+                            _ => tree.nodes[node].right = Some(child),
+                        }
+                        child
+                    }
+                };
+                tree.nodes[node].weight += weight;
+            }
+
+            tree.nodes[node].character = Some(*symbol);
+        }
+
+        Ok(tree)
+    }
+
+    /// Number of edges between the root and the deepest leaf.
+    pub fn depth(&self) -> usize {
+        let mut deepest = 0;
+        let mut stack: Vec<(usize, usize)> = self.root.map(|root| (root, 0)).into_iter().collect();
+
+        while let Some((index, depth)) = stack.pop() {
+            deepest = deepest.max(depth);
+            let node = &self.nodes[index];
+            stack.extend(node.left.map(|left| (left, depth + 1)));
+            stack.extend(node.right.map(|right| (right, depth + 1)));
+        }
+
+        deepest
+    }
+
+    /// See `generate_prefix_table`.
+    pub fn prefix_table(&self) -> Result<HashMap<S, (u32, u8)>, HuffError> {
+        let mut prefix_table = HashMap::new();
+
+        // special case: there is one distinct char in the input, so there is only one meaningful bit.
+        // as-is, it would get assigned a 0 for meaningful_bits, so we handle that here
+        if let Some(root) = self.root {
+            let meaningful_bits = match self.nodes[root].character {
+                Some(_) => 1,
+                None => 0,
+            };
+            self.collect_prefixes(root, 0, meaningful_bits, &mut prefix_table)?;
+        }
+
+        Ok(prefix_table)
+    }
+
+    /// Adds the code of every leaf under `node` to `prefix`, where `node` itself
+    /// has the code `state` that's `meaningful_bits` long.
+    pub fn collect_prefixes(
+        &self,
+        node: usize,
+        state: u32,
+        meaningful_bits: u8,
+        prefix: &mut HashMap<S, (u32, u8)>,
+    ) -> Result<(), HuffError> {
+        let mut stack = vec![(node, state, meaningful_bits)];
+
+        while let Some((index, state, meaningful_bits)) = stack.pop() {
+            let curr_node = &self.nodes[index];
+
+            if let Some(character) = curr_node.character {
+                if prefix.contains_key(&character) {
+                    return Err(HuffError::DuplicateSymbol(character.to_bits()));
+                }
+                prefix.insert(character, (state, meaningful_bits));
+                continue;
+            }
+
+            // the children need one more bit than we have room for
+            if meaningful_bits >= 32 {
+                return Err(HuffError::CodeTooLong(meaningful_bits + 1));
+            }
+
+            // right goes on first so the left side is visited first
+            if let Some(right) = curr_node.right {
+                stack.push((right, state << 1 | 1, meaningful_bits + 1));
+            }
+            if let Some(left) = curr_node.left {
+                stack.push((left, state << 1, meaningful_bits + 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the tree in preorder, see `generate_header`.
+    pub fn write_header(&self, bw: &mut BitWriter) -> Result<(), HuffError> {
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+
+        while let Some(index) = stack.pop() {
+            let curr_node = &self.nodes[index];
+
+            match curr_node.character {
+                Some(character) => {
+                    bw.write_bit(1)?;
+                    bw.write_bits(character.to_bits(), S::BITS);
+                }
+                None => bw.write_bit(0)?,
+            }
+
+            stack.extend(curr_node.right);
+            stack.extend(curr_node.left);
+        }
+
+        Ok(())
+    }
+
+    pub fn header_with_size(&self) -> Result<(u32, Vec<u8>), HuffError> {
+        let mut bw = BitWriter::new();
+        self.write_header(&mut bw)?;
+        let header = bw.get_vec()?;
+        let header_size = header.len() as u32;
+
+        Ok((header_size, header))
+    }
+
+    /// Reads a tree written by `write_header`. Leaves come back with a weight of 0.
+    pub fn read_header(br: &mut BitReader) -> Result<Self, HuffError> {
+        let mut tree = Self::new();
+        // internal nodes that are still missing a child
+        let mut pending: Vec<usize> = Vec::new();
+
+        loop {
+            let curr_bit = br.next().ok_or(HuffError::TruncatedHeader)?;
+
+            let character = if curr_bit == 1u8 {
+                let char_as_u32 = br
+                    .read_value(S::BITS)
+                    .map_err(|_| HuffError::TruncatedHeader)?;
+                Some(S::from_bits(char_as_u32)?)
+            } else {
+                None
+            };
+
+            let index = tree.push(TreeNode::new(character, 0));
+
+            match pending.last() {
+                None => tree.root = Some(index),
+                Some(&parent) if tree.nodes[parent].left.is_none() => {
+                    tree.nodes[parent].left = Some(index);
+                }
+                Some(&parent) => {
+                    tree.nodes[parent].right = Some(index);
+                    pending.pop();
+                }
+            }
+
+            if character.is_none() {
+                pending.push(index);
+            }
+
+            if pending.is_empty() {
+                return Ok(tree);
+            }
+        }
+    }
+
+    pub fn from_header(tree_data: &[u8]) -> Result<Self, HuffError> {
+        let mut br = BitReader::new(tree_data.to_vec());
+        Self::read_header(&mut br)
+    }
+
+    /// Copies a boxed tree into an arena.
+    pub fn from_boxed(node: &Option<Box<HuffNode<S>>>) -> Self {
+        let mut tree = Self::new();
+        let root = match node {
+            Some(root) => root,
+            None => return tree,
+        };
+
+        let root_index = tree.push(TreeNode::new(root.character, root.weight));
+        tree.root = Some(root_index);
+        let mut stack = vec![(root.as_ref(), root_index)];
+
+        while let Some((curr_node, index)) = stack.pop() {
+            if let Some(left) = &curr_node.left {
+                let child = tree.push(TreeNode::new(left.character, left.weight));
+                tree.nodes[index].left = Some(child);
+                stack.push((left, child));
+            }
+            if let Some(right) = &curr_node.right {
+                let child = tree.push(TreeNode::new(right.character, right.weight));
+                tree.nodes[index].right = Some(child);
+                stack.push((right, child));
+            }
+        }
+
+        tree
+    }
+
+    /// Builds the boxed version of the tree, for code still using `HuffNode`.
+    pub fn to_boxed(&self) -> Option<Box<HuffNode<S>>> {
+        let root = self.root?;
+
+        // every node shows up after its parent, so going backwards means
+        // both children are already built when we get to a node
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![root];
+        while let Some(index) = stack.pop() {
+            order.push(index);
+            stack.extend(self.nodes[index].left);
+            stack.extend(self.nodes[index].right);
+        }
+
+        let mut built: Vec<Option<Box<HuffNode<S>>>> =
+            (0..self.nodes.len()).map(|_| None).collect();
+        for &index in order.iter().rev() {
+            let node = &self.nodes[index];
+            let mut boxed = HuffNode::new(node.character, node.weight);
+            boxed.left = node.left.and_then(|left| built[left].take());
+            boxed.right = node.right.and_then(|right| built[right].take());
+            built[index] = Some(Box::new(boxed));
+        }
+
+        built[root].take()
+    }
+
+    /// Renders the tree as a Graphviz DOT graph. Leaves are boxes labeled with
+    /// their symbol (see `Symbol::escape`), weight and code, internal nodes are
+    /// circles with their weight and the code that leads to them, and edges
+    /// are labeled 0 for left and 1 for right.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph huffman {\n");
+        let mut stack: Vec<(usize, String)> = self
+            .root
+            .map(|root| (root, String::new()))
+            .into_iter()
+            .collect();
+
+        while let Some((index, code)) = stack.pop() {
+            let node = &self.nodes[index];
+            match node.character {
+                Some(character) => {
+                    // same special case as prefix_table: a lone symbol still gets a bit
+                    let code = if code.is_empty() { "0" } else { &code };
+                    dot += &format!(
+                        "    n{} [shape=box, label=\"{}\\n{}\\n{}\"];\n",
+                        index,
+                        dot_escape(&character.escape()),
+                        node.weight,
+                        code
+                    );
+                }
+                // the root has no code to show
+                None if code.is_empty() => {
+                    dot += &format!(
+                        "    n{} [shape=circle, label=\"{}\"];\n",
+                        index, node.weight
+                    );
+                }
+                None => {
+                    dot += &format!(
+                        "    n{} [shape=circle, label=\"{}\\n{}\"];\n",
+                        index, node.weight, code
+                    );
+                }
+            }
+
+            let children = [(node.left, '0'), (node.right, '1')];
+            for (child, bit) in children {
+                if let Some(child) = child {
+                    dot += &format!("    n{} -> n{} [label=\"{}\"];\n", index, child, bit);
+                }
+            }
+            // right goes on first so the left side is written first
+            for (child, bit) in children.into_iter().rev() {
+                if let Some(child) = child {
+                    stack.push((child, format!("{}{}", code, bit)));
+                }
+            }
+        }
+
+        dot += "}\n";
+        dot
+    }
+}
+
+/// The most the weights of a tree can add up to. Package-merge can put a copy
+/// of every weight into a package at each of its 32 levels, so this leaves
+/// room for 32 times the total.
+const MAX_TOTAL_WEIGHT: u64 = u64::MAX >> 5;
+
+/// `freq` as is if its counts add up to at most `MAX_TOTAL_WEIGHT`, and
+/// otherwise with every count shifted right just far enough that they do.
+/// Counts never go below 1, so every symbol still gets a code, and the
+/// shape of the tree changes about as little as it can.
+fn scale_weights<S: Symbol>(freq: &Freq<S>) -> Cow<'_, Freq<S>> {
+    let total_after = |shift: u32| -> u128 {
+        freq.counter
+            .values()
+            .map(|&count| (count >> shift).max(1) as u128)
+            .sum()
+    };
+    if total_after(0) <= MAX_TOTAL_WEIGHT as u128 {
+        return Cow::Borrowed(freq);
+    }
+
+    let shift = (1..64)
+        .find(|&shift| total_after(shift) <= MAX_TOTAL_WEIGHT as u128)
+        .unwrap_or(63);
+    let mut scaled = Freq::new();
+    scaled.counter = freq
+        .counter
+        .iter()
+        .map(|(&symbol, &count)| (symbol, (count >> shift).max(1)))
+        .collect();
+    Cow::Owned(scaled)
+}
+
+/// Escapes backslashes and quotes so `label` can go in a DOT string.
+fn dot_escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// heap entry for HuffmanTree::from_freq, it orders exactly like HuffNode does
+struct HeapEntry<S> {
+    weight: u64,
+    character: Option<S>,
+    index: usize,
+}
+
+impl<S: Ord> PartialEq for HeapEntry<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<S: Ord> Eq for HeapEntry<S> {}
+
+impl<S: Ord> PartialOrd for HeapEntry<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: Ord> Ord for HeapEntry<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        heap_order(self.weight, &self.character, other.weight, &other.character)
+    }
+}
+
+#[derive(Debug)]
+pub struct HuffNode<S = char> {
+    pub character: Option<S>,
+    pub weight: u64,
+    pub left: Option<Box<HuffNode<S>>>,
+    pub right: Option<Box<HuffNode<S>>>,
+}
+
+impl<S> HuffNode<S> {
+    pub fn new(character: Option<S>, weight: u64) -> Self {
         Self {
             character,
             weight,
@@ -73,46 +605,56 @@ impl HuffNode {
 
 // Implementing these traits so that nodes in min-heap
 // order as they are added to the BinaryHeap
-impl PartialEq for HuffNode {
+impl<S> PartialEq for HuffNode<S> {
     fn eq(&self, other: &Self) -> bool {
         self.weight == other.weight
     }
 }
 
-impl Eq for HuffNode {}
+impl<S> Eq for HuffNode<S> {}
 
-impl PartialOrd for HuffNode {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+impl<S: Ord> PartialOrd for HuffNode<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Ord for HuffNode {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        let cmp_result = other.weight.cmp(&self.weight);
+impl<S: Ord> Ord for HuffNode<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        heap_order(self.weight, &self.character, other.weight, &other.character)
+    }
+}
 
-        if cmp_result != std::cmp::Ordering::Equal {
-            // weights aren't equal, return cmp result to get min-heap by weight behavior
-            return cmp_result;
-        }
+fn heap_order<S: Ord>(
+    weight: u64,
+    character: &Option<S>,
+    other_weight: u64,
+    other_character: &Option<S>,
+) -> Ordering {
+    let cmp_result = other_weight.cmp(&weight);
 
-        match (self.character, other.character) {
-            // weights are equal, so we order lexicographically by character if possible
-            (Some(c1), Some(c2)) => c2.cmp(&c1), // Returns Less if c2 < c1
-            (Some(_), None) => std::cmp::Ordering::Greater, // Any char value is greater than no char value
-            (None, Some(_)) => std::cmp::Ordering::Less, // None value is Less than some char value
-            (None, None) => std::cmp::Ordering::Equal,
-        }
+    if cmp_result != Ordering::Equal {
+        // weights aren't equal, return cmp result to get min-heap by weight behavior
+        return cmp_result;
+    }
+
+    match (character, other_character) {
+        // weights are equal, so we order lexicographically by character if possible
+        (Some(c1), Some(c2)) => c2.cmp(c1), // Returns Less if c2 < c1
+        (Some(_), None) => Ordering::Greater, // Any char value is greater than no char value
+        (None, Some(_)) => Ordering::Less,  // None value is Less than some char value
+        (None, None) => Ordering::Equal,
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::encoding::encoding::generate_prefix_table;
     use crate::encoding::test_cases;
 
     use super::*;
 
-    pub fn verify_tree(node: &Option<Box<HuffNode>>, freq: &Freq) {
+    pub fn verify_tree<S: Symbol>(node: &Option<Box<HuffNode<S>>>, freq: &Freq<S>) {
         let curr_node = node.as_ref().unwrap();
         println!("printing node:");
         println!("{:?}", curr_node);
@@ -127,8 +669,8 @@ mod tests {
 
             assert_eq!(curr_node.weight, left_weight + right_weight);
 
-            verify_tree(&curr_node.left, &freq);
-            verify_tree(&curr_node.right, &freq);
+            verify_tree(&curr_node.left, freq);
+            verify_tree(&curr_node.right, freq);
         }
     }
     #[test]
@@ -160,16 +702,16 @@ mod tests {
         min_heap.push(&light_node);
 
         assert_eq!(min_heap.len(), 2);
-        assert_eq!(min_heap.pop().unwrap(), Some(&light_node).unwrap());
-        assert_eq!(min_heap.pop().unwrap(), Some(&heavy_node).unwrap());
+        assert_eq!(min_heap.pop().unwrap(), &light_node);
+        assert_eq!(min_heap.pop().unwrap(), &heavy_node);
     }
 
     #[test]
     fn test_single_node_tree() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaa".as_bytes();
 
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
 
         let root = generate_tree(&freq);
 
@@ -184,10 +726,10 @@ mod tests {
 
     #[test]
     fn test_two_node_tree() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "acc".as_bytes();
 
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
 
         let root = generate_tree(&freq);
 
@@ -218,10 +760,10 @@ mod tests {
 
     #[test]
     fn test_three_node_tree() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "aaabbcd".as_bytes();
 
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
 
         let root = generate_tree(&freq);
 
@@ -232,24 +774,266 @@ mod tests {
     // write a test to lock in an order and fix
     #[test]
     fn test_tree_no_duplicate_characters() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = "abcd".as_bytes();
 
-        freq.update(test_input);
+        freq.update(test_input).unwrap();
 
         let root = generate_tree(&freq);
 
         verify_tree(&root, &freq);
     }
 
+    fn fibonacci_freq(symbols: usize) -> Freq<u8> {
+        let mut freq = Freq::new();
+        let (mut a, mut b) = (1u64, 1u64);
+        for symbol in 0..symbols as u8 {
+            freq.counter.insert(symbol, a);
+            (a, b) = (b, a + b);
+        }
+        freq
+    }
+
+    #[test]
+    fn test_fibonacci_tree_too_deep() {
+        let freq = fibonacci_freq(40);
+
+        let root = generate_tree(&freq);
+
+        assert_eq!(tree_depth(&root), 39);
+        assert!(matches!(
+            generate_prefix_table(root),
+            Err(HuffError::CodeTooLong(33))
+        ));
+    }
+
+    #[test]
+    fn test_fibonacci_length_limited_tree() {
+        let freq = fibonacci_freq(40);
+
+        for max_code_length in [12, 15, 24, 32] {
+            let root = generate_tree_with_max_length(&freq, max_code_length).unwrap();
+
+            assert_eq!(tree_depth(&root), max_code_length as usize);
+            verify_tree(&root, &freq);
+            let prefix_table = generate_prefix_table(root).unwrap();
+            assert_eq!(prefix_table.len(), 40);
+        }
+    }
+
+    #[test]
+    fn test_counts_above_u32_max() {
+        let mut freq: Freq<u8> = Freq::new();
+        freq.counter = HashMap::from([(b'a', 5 << 32), (b'b', 3 << 32), (b'c', 7), (b'd', 1)]);
+
+        let root = generate_tree(&freq);
+
+        verify_tree(&root, &freq);
+        assert_eq!(root.as_ref().unwrap().weight, (8 << 32) + 8);
+        let prefix_table = generate_prefix_table(root).unwrap();
+        assert_eq!(prefix_table[&b'a'].1, 1);
+        assert_eq!(prefix_table[&b'b'].1, 2);
+    }
+
+    #[test]
+    fn test_weights_near_u64_max_are_scaled() {
+        let mut freq: Freq<u8> = Freq::new();
+        freq.counter = HashMap::from([
+            (b'a', u64::MAX),
+            (b'b', u64::MAX),
+            (b'c', u64::MAX / 2),
+            (b'd', 1),
+        ]);
+
+        let tree = HuffmanTree::from_freq(&freq);
+
+        let root = tree.node(tree.root().unwrap());
+        assert!(root.weight <= MAX_TOTAL_WEIGHT);
+        let lengths: Vec<u8> = [b'a', b'b', b'c', b'd']
+            .iter()
+            .map(|symbol| tree.prefix_table().unwrap()[symbol].1)
+            .collect();
+        assert_eq!(lengths, vec![2, 1, 3, 3]);
+    }
+
+    #[test]
+    fn test_length_limited_tree_with_huge_weights() {
+        let mut freq = fibonacci_freq(40);
+        for count in freq.counter.values_mut() {
+            *count <<= 35;
+        }
+
+        let tree = HuffmanTree::with_max_length(&freq, 16).unwrap();
+
+        assert_eq!(tree.depth(), 16);
+        assert_eq!(tree.prefix_table().unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_shallow_tree_is_not_limited() {
+        let mut freq: Freq = Freq::new();
+        freq.update(test_cases::SAMPLE_TEST.as_bytes()).unwrap();
+        let expected = generate_prefix_table(generate_tree(&freq)).unwrap();
+
+        let root = generate_tree_with_max_length(&freq, 32).unwrap();
+
+        assert_eq!(generate_prefix_table(root).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_tree_from_code_lengths() {
+        let mut freq: Freq = Freq::new();
+        freq.update("aaaabbc".as_bytes()).unwrap();
+        let code_lengths = HashMap::from([('a', 1), ('b', 2), ('c', 2)]);
+
+        let root = tree_from_code_lengths(&code_lengths, &freq).unwrap();
+
+        verify_tree(&root, &freq);
+        assert_eq!(root.as_ref().unwrap().weight, 7);
+        let prefix_table = generate_prefix_table(root).unwrap();
+        assert_eq!(prefix_table.get(&'a').unwrap(), &(0b0, 1));
+        assert_eq!(prefix_table.get(&'b').unwrap(), &(0b10, 2));
+        assert_eq!(prefix_table.get(&'c').unwrap(), &(0b11, 2));
+    }
+
     #[test]
     fn test_tree_bigger_string() {
-        let mut freq = Freq::new();
+        let mut freq: Freq = Freq::new();
         let test_input = test_cases::SAMPLE_TEST;
-        freq.update(test_input.as_bytes());
+        freq.update(test_input.as_bytes()).unwrap();
 
         let root = generate_tree(&freq);
 
         verify_tree(&root, &freq);
     }
+
+    #[test]
+    fn test_to_dot() {
+        let mut freq: Freq = Freq::new();
+        freq.update("aaaabbc".as_bytes()).unwrap();
+        let code_lengths = HashMap::from([('a', 1), ('b', 2), ('c', 2)]);
+
+        let dot = HuffmanTree::from_code_lengths(&code_lengths, &freq)
+            .unwrap()
+            .to_dot();
+
+        assert!(dot.starts_with("digraph huffman {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains(r#"[shape=circle, label="7"]"#));
+        assert!(dot.contains(r#"[shape=circle, label="3\n1"]"#));
+        assert!(dot.contains(r#"[shape=box, label="a\n4\n0"]"#));
+        assert!(dot.contains(r#"[shape=box, label="b\n2\n10"]"#));
+        assert!(dot.contains(r#"[shape=box, label="c\n1\n11"]"#));
+        assert_eq!(dot.matches(" -> ").count(), 4);
+        assert_eq!(dot.matches(r#"[label="0"]"#).count(), 2);
+    }
+
+    #[test]
+    fn test_to_dot_escapes_labels() {
+        let mut freq: Freq = Freq::new();
+        freq.update("\"\"\n".as_bytes()).unwrap();
+
+        let dot = tree_to_dot(&generate_tree(&freq));
+
+        assert!(dot.contains(r#"label="\"\n2\n"#));
+        assert!(dot.contains(r#"label="\\n\n1\n"#));
+    }
+
+    #[test]
+    fn test_to_dot_single_symbol() {
+        let mut freq: Freq = Freq::new();
+        freq.update("aaa".as_bytes()).unwrap();
+
+        let dot = tree_to_dot(&generate_tree(&freq));
+
+        assert!(dot.contains(r#"label="a\n3\n0""#));
+        assert!(!dot.contains(" -> "));
+        assert_eq!(tree_to_dot::<char>(&None), "digraph huffman {\n}\n");
+    }
+
+    #[test]
+    fn test_arena_matches_boxed_tree() {
+        let mut freq: Freq = Freq::new();
+        freq.update(test_cases::SAMPLE_TEST.as_bytes()).unwrap();
+
+        let tree = HuffmanTree::from_freq(&freq);
+        let root = tree.to_boxed();
+
+        verify_tree(&root, &freq);
+        assert_eq!(tree.len(), freq.counter.len() * 2 - 1);
+        assert_eq!(tree.depth(), tree_depth(&root));
+        assert_eq!(
+            HuffmanTree::from_boxed(&root).prefix_table().unwrap(),
+            tree.prefix_table().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_arena_empty_tree() {
+        let freq: Freq = Freq::new();
+
+        let tree = HuffmanTree::from_freq(&freq);
+
+        assert!(tree.is_empty());
+        assert_eq!(tree.root(), None);
+        assert!(tree.to_boxed().is_none());
+        assert!(tree.prefix_table().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_arena_header_round_trip() {
+        let mut freq: Freq = Freq::new();
+        freq.update(test_cases::SAMPLE_TEST.as_bytes()).unwrap();
+        let tree = HuffmanTree::from_freq(&freq);
+
+        let (header_size, header) = tree.header_with_size().unwrap();
+        let decoded: HuffmanTree = HuffmanTree::from_header(&header).unwrap();
+
+        assert_eq!(header_size as usize, header.len());
+        assert_eq!(
+            decoded.prefix_table().unwrap(),
+            tree.prefix_table().unwrap()
+        );
+    }
+
+    // a header describing a chain of internal nodes, each with a leaf on the left.
+    // the recursive version blew the stack on these long before reaching the end
+    fn degenerate_header(depth: u32) -> Vec<u8> {
+        let mut bw = BitWriter::new();
+        for i in 0..depth {
+            bw.write_bit(0).unwrap();
+            bw.write_bit(1).unwrap();
+            bw.write_bits(i % 256, 8);
+        }
+        bw.write_bit(1).unwrap();
+        bw.write_bits(0, 8);
+        bw.get_vec().unwrap()
+    }
+
+    #[test]
+    fn test_arena_degenerate_tree() {
+        let depth = 500_000;
+        let header = degenerate_header(depth);
+
+        let tree: HuffmanTree<u8> = HuffmanTree::from_header(&header).unwrap();
+
+        assert_eq!(tree.depth(), depth as usize);
+        assert!(matches!(
+            tree.prefix_table(),
+            Err(HuffError::CodeTooLong(33))
+        ));
+        let (_, rewritten) = tree.header_with_size().unwrap();
+        assert_eq!(rewritten, header);
+    }
+
+    #[test]
+    fn test_arena_truncated_header() {
+        let mut header = degenerate_header(10);
+        header.pop();
+
+        assert!(matches!(
+            HuffmanTree::<u8>::from_header(&header),
+            Err(HuffError::TruncatedHeader)
+        ));
+    }
 }
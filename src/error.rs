@@ -0,0 +1,198 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::encoding::symbol::Alphabet;
+
+/// Everything that can go wrong while encoding or decoding.
+#[derive(Debug)]
+pub enum HuffError {
+    /// Reading the input or writing the output failed.
+    Io(io::Error),
+    /// There was nothing to encode, or the compressed input was empty.
+    EmptyInput,
+    /// The compressed input ended before the tree header was complete.
+    TruncatedHeader,
+    /// The compressed input ended in the middle of the encoded data.
+    TruncatedData,
+    /// A leaf in the tree header isn't a valid value for the alphabet.
+    InvalidScalarValue(u32),
+    /// The input isn't valid UTF-8, so it can't be encoded as chars.
+    InvalidUtf8,
+    /// The flags byte in the file header has bits set that we don't know.
+    InvalidMode(u8),
+    /// The encoded data contains a bit sequence that isn't in the prefix table.
+    UnknownPrefix,
+    /// The input contains a symbol that isn't in the prefix table.
+    UnknownSymbol(u32),
+    /// The same symbol shows up twice in a tree.
+    DuplicateSymbol(u32),
+    /// Two symbols were assigned the same prefix.
+    DuplicatePrefix { prefix: u32, length: u8 },
+    /// The code lengths in a canonical header don't describe a valid set of codes.
+    InvalidCodeLengths,
+    /// The tree is deeper than a prefix can hold.
+    CodeTooLong(u8),
+    /// The maximum code length is outside 1 to 32 bits, or too short to give
+    /// every symbol its own code.
+    MaxCodeLengthTooSmall(u8),
+    /// Something other than a 0 or 1 was written as a bit.
+    InvalidBit(u8),
+    /// The byte at the end of a version 1 block isn't a bit position from 0 to 7.
+    TrailingByteMismatch(u8),
+    /// A block in the compressed input starts with a type byte we don't know.
+    InvalidBlockType(u8),
+    /// The compressed input doesn't start with the huff magic bytes.
+    NotHuff,
+    /// The file header says it's a format version we can't read.
+    UnsupportedVersion(u8),
+    /// The decoded output isn't as long as the file header says the input was.
+    LengthMismatch { expected: u64, actual: u64 },
+    /// The decoded output doesn't match the checksum stored with the input.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// A block wants to reuse the last tree, but no block before it had one.
+    NoPreviousTree,
+    /// The block size is outside `MIN_BLOCK_SIZE` to `MAX_BLOCK_SIZE`.
+    InvalidBlockSize(usize),
+    /// The input was coded with a shared tree, but the decoder wasn't given one.
+    MissingSharedTree(u32),
+    /// The input was coded with a different shared tree than the one the decoder has.
+    SharedTreeMismatch { expected: u32, actual: u32 },
+    /// Saved symbol counts are for a different alphabet than the one asked for.
+    AlphabetMismatch {
+        expected: Alphabet,
+        actual: Alphabet,
+    },
+}
+
+impl fmt::Display for HuffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HuffError::Io(err) => write!(f, "i/o error: {}", err),
+            HuffError::EmptyInput => write!(f, "input is empty"),
+            HuffError::TruncatedHeader => write!(f, "tree header is truncated"),
+            HuffError::TruncatedData => write!(f, "encoded data is truncated"),
+            HuffError::InvalidScalarValue(value) => write!(
+                f,
+                "header contains {:#x}, which is not a valid symbol",
+                value
+            ),
+            HuffError::InvalidUtf8 => write!(
+                f,
+                "input is not valid UTF-8, use the bytes alphabet to encode it"
+            ),
+            HuffError::InvalidMode(mode) => write!(f, "unknown flags byte {:#04x}", mode),
+            HuffError::UnknownPrefix => {
+                write!(f, "encoded data contains a prefix that is not in the tree")
+            }
+            HuffError::UnknownSymbol(value) => {
+                write!(f, "symbol {:#x} is not in the prefix table", value)
+            }
+            HuffError::DuplicateSymbol(value) => {
+                write!(f, "symbol {:#x} appears more than once in the tree", value)
+            }
+            HuffError::DuplicatePrefix { prefix, length } => write!(
+                f,
+                "prefix {:0width$b} is assigned to more than one symbol",
+                prefix,
+                width = *length as usize
+            ),
+            HuffError::InvalidCodeLengths => {
+                write!(f, "header code lengths do not describe a valid prefix code")
+            }
+            HuffError::CodeTooLong(length) => write!(
+                f,
+                "tree needs {}-bit codes, but codes are limited to 32 bits",
+                length
+            ),
+            HuffError::MaxCodeLengthTooSmall(length) => write!(
+                f,
+                "a maximum code length of {} bits can't fit every symbol, it must be 1 to 32 bits",
+                length
+            ),
+            HuffError::InvalidBit(bit) => write!(f, "{} is not a valid bit", bit),
+            HuffError::TrailingByteMismatch(byte) => write!(
+                f,
+                "trailing byte {} is not a valid bit position in the last data byte",
+                byte
+            ),
+            HuffError::InvalidBlockType(byte) => write!(f, "unknown block type {:#04x}", byte),
+            HuffError::NotHuff => write!(f, "not a huff file"),
+            HuffError::UnsupportedVersion(version) => {
+                write!(f, "huff format version {} is not supported", version)
+            }
+            HuffError::LengthMismatch { expected, actual } => write!(
+                f,
+                "decoded {} bytes, but the original was {} bytes",
+                actual, expected
+            ),
+            HuffError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch: decoded data has CRC-32 {:08x}, expected {:08x}",
+                actual, expected
+            ),
+            HuffError::NoPreviousTree => {
+                write!(f, "block reuses the previous tree, but there isn't one")
+            }
+            HuffError::InvalidBlockSize(size) => write!(
+                f,
+                "a block size of {} bytes isn't supported, it must be 64 KiB to 4 MiB",
+                size
+            ),
+            HuffError::MissingSharedTree(fingerprint) => write!(
+                f,
+                "input was coded with shared tree {:08x}, but no tree was given",
+                fingerprint
+            ),
+            HuffError::SharedTreeMismatch { expected, actual } => write!(
+                f,
+                "input was coded with shared tree {:08x}, but the tree given is {:08x}",
+                expected, actual
+            ),
+            HuffError::AlphabetMismatch { expected, actual } => write!(
+                f,
+                "counts are for the {} alphabet, but {} were wanted",
+                alphabet_name(*actual),
+                alphabet_name(*expected)
+            ),
+        }
+    }
+}
+
+fn alphabet_name(alphabet: Alphabet) -> &'static str {
+    match alphabet {
+        Alphabet::Chars => "chars",
+        Alphabet::Bytes => "bytes",
+    }
+}
+
+impl Error for HuffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HuffError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HuffError {
+    fn from(err: io::Error) -> Self {
+        // a HuffError that went through an io::Write or io::Read impl comes back out as itself
+        if err.get_ref().is_some_and(|inner| inner.is::<HuffError>()) {
+            let inner = err.into_inner().unwrap();
+            return *inner.downcast::<HuffError>().unwrap();
+        }
+        HuffError::Io(err)
+    }
+}
+
+/// Lets `HuffError`s come back out of `io::Write`/`io::Read` impls. I/O errors
+/// are unwrapped again, everything else is reported as invalid data.
+impl From<HuffError> for io::Error {
+    fn from(err: HuffError) -> Self {
+        match err {
+            HuffError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
@@ -1 +1,38 @@
+//! Huffman encoder/decoder.
+//!
+//! The easiest way in is [`compress`] and [`decompress`], which work on
+//! in-memory buffers. [`Encoder`] and [`Decoder`] do the same thing over any
+//! reader/writer pair, [`HuffWriter`] and [`HuffReader`] do it a block at a
+//! time through `io::Write` and `io::Read`, and the building blocks they wrap (frequency counting,
+//! tree construction, prefix tables, headers) live under [`encoding`].
+//!
+//! ```
+//! let input = "a man a plan a canal panama".as_bytes();
+//!
+//! let compressed = huffman::compress(input)?;
+//! let decompressed = huffman::decompress(&compressed)?;
+//!
+//! assert_eq!(decompressed, input);
+//! # Ok::<(), huffman::HuffError>(())
+//! ```
+//!
+//! Nothing in the pipeline panics on bad input; every failure comes back as a
+//! [`HuffError`].
 
+mod codec;
+pub mod encoding;
+mod error;
+mod reader;
+mod report;
+mod shared_tree;
+mod writer;
+
+pub use codec::{compress, decompress, Decoder, EncodeSummary, Encoder};
+pub use encoding::decoding::DecodeStrategy;
+pub use encoding::huffio::HeaderFormat;
+pub use encoding::symbol::Alphabet;
+pub use error::HuffError;
+pub use reader::HuffReader;
+pub use report::CompressionReport;
+pub use shared_tree::SharedTree;
+pub use writer::{HuffWriter, BLOCK_SIZE, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
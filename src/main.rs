@@ -1,56 +1,1539 @@
-mod encoding;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
-use std::fs::File;
-use std::io::{Seek, SeekFrom};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 
-use clap::Parser;
-
-use encoding::encoding::{generate_prefix_table, get_encoded_data, get_tree_header_with_size};
-use encoding::frequency::Freq;
-use encoding::huffio::{decompress_data, write_compressed_data, write_size_header};
-use encoding::tree::generate_tree;
+use huffman::encoding::canonical::get_code_lengths;
+use huffman::encoding::encoding::{code_table, CodeEntry};
+use huffman::encoding::frequency::Freq;
+use huffman::encoding::huffio::{read_block_info, read_file_header, write_file_header, BlockType};
+use huffman::encoding::symbol::Symbol;
+use huffman::encoding::tree::HuffmanTree;
+use huffman::{
+    Alphabet, CompressionReport, Decoder, Encoder, HeaderFormat, HuffError, HuffWriter, SharedTree,
+};
 
 #[derive(Parser)]
 #[command(name = "huff")]
 #[command(about = "huffman encoder/decoder", long_about = None)]
-
 struct Cli {
-    file_name: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress files, each to <INPUT>_huff unless -o says otherwise.
+    /// With - as the input, reads stdin and writes stdout
+    Compress {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Where to write the compressed file, - for stdout
+        #[arg(short, long, conflicts_with = "recursive")]
+        output: Option<PathBuf>,
+
+        /// Write to stdout
+        #[arg(short = 'c', long, conflicts_with_all = ["output", "recursive"])]
+        stdout: bool,
+
+        /// Print sizes, entropy and code lengths for each file. They go to
+        /// stdout, or stderr if the compressed data does
+        #[arg(long)]
+        stats: bool,
+
+        /// Print the stats as JSON instead, one object per line
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        files: FileOptions,
+
+        #[command(flatten)]
+        options: CompressOptions,
+    },
+    /// Decompress files, each to <INPUT> without its _huff suffix unless -o
+    /// says otherwise. With - as the input, reads stdin and writes stdout
+    Decompress {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Where to write the decompressed file, - for stdout
+        #[arg(short, long, conflicts_with = "recursive")]
+        output: Option<PathBuf>,
+
+        /// Write to stdout
+        #[arg(short = 'c', long, conflicts_with_all = ["output", "recursive"])]
+        stdout: bool,
+
+        #[command(flatten)]
+        files: FileOptions,
+
+        #[command(flatten)]
+        options: DecodeOptions,
+    },
+    /// Check that compressed files decode cleanly, without writing anything.
+    /// - reads stdin
+    Test {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        #[command(flatten)]
+        files: FileOptions,
+
+        #[command(flatten)]
+        options: DecodeOptions,
+    },
+    /// Show what's in compressed files' headers and blocks. - reads stdin
+    Info {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Print the tree that compressing a file in one block would build.
+    /// - reads stdin
+    Tree {
+        input: PathBuf,
+
+        /// How to print the tree. dot can be rendered with Graphviz, htree is
+        /// a binary tree file for --tree
+        #[arg(long, value_enum, default_value_t = TreeFormat::Dot)]
+        format: TreeFormat,
+
+        #[command(flatten)]
+        options: TreeOptions,
+    },
+    /// Print the code every symbol in a file gets, along with how often it
+    /// comes up. - reads stdin
+    Codes {
+        input: PathBuf,
+
+        /// How to print the codes
+        #[arg(long, value_enum, default_value_t = CodesFormat::Table)]
+        format: CodesFormat,
+
+        /// Which order to list the symbols in
+        #[arg(long, value_enum, default_value_t = CodesOrder::Frequency)]
+        sort: CodesOrder,
+
+        #[command(flatten)]
+        options: TreeOptions,
+    },
+    /// Count the symbols in a set of files and save the counts, so train can
+    /// put together counts made on different machines
+    Count {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Where to write the counts
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// What to treat as a symbol. chars only works on valid UTF-8 input
+        #[arg(long, value_enum, default_value_t = CliAlphabet::Bytes)]
+        alphabet: CliAlphabet,
+    },
+    /// Build a shared tree for --tree from the symbols in a set of sample
+    /// files, and show how well it codes them
+    Train {
+        #[arg(required_unless_present = "counts")]
+        inputs: Vec<PathBuf>,
+
+        /// Counts saved by count, added to the ones from the inputs. Can be
+        /// given more than once
+        #[arg(long, value_name = "FILE")]
+        counts: Vec<PathBuf>,
+
+        /// Where to write the tree
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// A file that wasn't trained on, to see how the tree does on new input.
+        /// Can be given more than once
+        #[arg(long, value_name = "FILE")]
+        held_out: Vec<PathBuf>,
+
+        /// Added to the count of every byte, or every ASCII character with
+        /// --alphabet chars, so ones the samples don't have still get a code.
+        /// Blocks with symbols that have no code get a tree of their own
+        #[arg(long, default_value_t = 1)]
+        smoothing: u64,
+
+        #[command(flatten)]
+        options: TreeOptions,
+    },
+}
+
+#[derive(Args)]
+struct FileOptions {
+    /// Go into directories and handle every file under them. Compressing skips
+    /// files that end in _huff, and the other commands only look at those
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// How many files to work on at once. Defaults to the number of CPUs
+    #[arg(long)]
+    threads: Option<NonZeroUsize>,
+}
+
+/// Everything that decides what tree a block gets.
+#[derive(Args)]
+struct TreeOptions {
+    /// What to treat as a symbol. chars only works on valid UTF-8 input
+    #[arg(long, value_enum, default_value_t = CliAlphabet::Bytes)]
+    alphabet: CliAlphabet,
+
+    /// How to store the code table. canonical only stores code lengths and is smaller
+    #[arg(long, value_enum, default_value_t = CliHeader::Canonical)]
+    header: CliHeader,
+
+    /// Longest code allowed, from 1 to 32 bits
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u8).range(1..=32))]
+    max_code_length: u8,
+}
+
+#[derive(Args)]
+struct CompressOptions {
+    #[command(flatten)]
+    tree: TreeOptions,
+
+    /// How many KiB of input get their own tree, from 64 to 4096
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u16).range(64..=4096))]
+    block_size: u16,
+
+    /// Code each block with a tree that adapts as symbols are read, instead of
+    /// storing one up front. Ignores --header and --max-code-length
+    #[arg(long)]
+    adaptive: bool,
+
+    /// Code with a shared tree from `huff tree --format htree` instead of
+    /// storing a tree in the file, where its symbols allow. Its alphabet and
+    /// header format win over --alphabet and --header
+    #[arg(long = "tree", value_name = "FILE")]
+    shared_tree: Option<PathBuf>,
+}
+
+impl CompressOptions {
+    fn encoder(&self) -> Result<Encoder, HuffError> {
+        let mut encoder = Encoder::new()
+            .alphabet(self.tree.alphabet.into())
+            .header(self.tree.header.into())
+            .max_code_length(self.tree.max_code_length)
+            .block_size(self.block_size as usize * 1024)
+            .adaptive(self.adaptive);
+        if let Some(path) = &self.shared_tree {
+            encoder = encoder.shared_tree(load_shared_tree(path)?);
+        }
+        Ok(encoder)
+    }
+}
+
+#[derive(Args)]
+struct DecodeOptions {
+    /// The shared tree the files were compressed with
+    #[arg(long = "tree", value_name = "FILE")]
+    shared_tree: Option<PathBuf>,
+}
+
+impl DecodeOptions {
+    fn decoder(&self) -> Result<Decoder, HuffError> {
+        let mut decoder = Decoder::new();
+        if let Some(path) = &self.shared_tree {
+            decoder = decoder.shared_tree(load_shared_tree(path)?);
+        }
+        Ok(decoder)
+    }
+}
+
+fn load_shared_tree(path: &Path) -> Result<SharedTree, HuffError> {
+    File::open(path)
+        .map_err(HuffError::from)
+        .and_then(|file| SharedTree::load(BufReader::new(file)))
+        .map_err(|err| report(path, err))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CliAlphabet {
+    Bytes,
+    Chars,
+}
+
+impl From<CliAlphabet> for Alphabet {
+    fn from(alphabet: CliAlphabet) -> Self {
+        match alphabet {
+            CliAlphabet::Bytes => Alphabet::Bytes,
+            CliAlphabet::Chars => Alphabet::Chars,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CliHeader {
+    Tree,
+    Canonical,
+}
+
+impl From<CliHeader> for HeaderFormat {
+    fn from(header: CliHeader) -> Self {
+        match header {
+            CliHeader::Tree => HeaderFormat::Tree,
+            CliHeader::Canonical => HeaderFormat::Canonical,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TreeFormat {
+    Dot,
+    Htree,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CodesFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum CodesOrder {
+    /// Most frequent first
+    Frequency,
+    /// Left to right across the tree
+    Code,
+}
+
+/// Exit code for bad arguments. clap would use 2, which is taken by io errors.
+const USAGE_ERROR: u8 = 1;
+
+/// Each kind of failure gets its own exit code so scripts can tell them apart.
+/// 1 is left for usage errors, see `USAGE_ERROR`.
+fn exit_code(err: &HuffError) -> u8 {
+    match err {
+        HuffError::Io(_) => 2,
+        HuffError::EmptyInput => 3,
+        HuffError::TruncatedHeader => 4,
+        HuffError::TruncatedData => 5,
+        HuffError::InvalidScalarValue(_) => 6,
+        HuffError::UnknownPrefix => 7,
+        HuffError::UnknownSymbol(_) => 8,
+        HuffError::DuplicateSymbol(_) => 9,
+        HuffError::DuplicatePrefix { .. } => 10,
+        HuffError::CodeTooLong(_) => 11,
+        HuffError::InvalidBit(_) => 12,
+        HuffError::TrailingByteMismatch(_) => 13,
+        HuffError::InvalidUtf8 => 14,
+        HuffError::InvalidMode(_) => 15,
+        HuffError::InvalidCodeLengths => 16,
+        HuffError::MaxCodeLengthTooSmall(_) => 17,
+        HuffError::InvalidBlockType(_) => 18,
+        HuffError::NotHuff => 19,
+        HuffError::UnsupportedVersion(_) => 20,
+        HuffError::LengthMismatch { .. } => 21,
+        HuffError::ChecksumMismatch { .. } => 22,
+        HuffError::NoPreviousTree => 23,
+        HuffError::InvalidBlockSize(_) => 24,
+        HuffError::MissingSharedTree(_) => 25,
+        HuffError::SharedTreeMismatch { .. } => 26,
+        HuffError::AlphabetMismatch { .. } => 27,
+    }
 }
 
-fn main() {
-    let cli = Cli::parse();
-    let mut freq = Freq::new();
+const SUFFIX: &str = "_huff";
+const STDIO: &str = "-";
 
-    let mut file = match &cli.file_name {
-        Some(file_name) => File::open(&file_name)
-            .unwrap_or_else(|_| panic!("Failed while opening file {}", &file_name)),
-        None => {
-            panic!("Must pass valid file path to huff")
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO
+}
+
+fn display_name(path: &Path) -> String {
+    match is_stdio(path) {
+        true => "<stdin>".to_string(),
+        false => path.display().to_string(),
+    }
+}
+
+fn compressed_name(input: &Path) -> PathBuf {
+    let mut name = input.as_os_str().to_owned();
+    name.push(SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Whether the file name ends in _huff, looked at as bytes so non-UTF-8
+/// names work.
+fn has_suffix(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().ends_with(SUFFIX.as_bytes()))
+}
+
+/// `name` without its _huff suffix, if it has one.
+#[cfg(unix)]
+fn strip_suffix(name: &OsStr) -> Option<&OsStr> {
+    use std::os::unix::ffi::OsStrExt;
+
+    name.as_bytes()
+        .strip_suffix(SUFFIX.as_bytes())
+        .map(OsStr::from_bytes)
+}
+
+/// `name` without its _huff suffix, if it has one. Only UTF-8 names can be
+/// cut up safely here.
+#[cfg(not(unix))]
+fn strip_suffix(name: &OsStr) -> Option<&OsStr> {
+    name.to_str()?.strip_suffix(SUFFIX).map(OsStr::new)
+}
+
+/// The input without the _huff suffix on its file name, or with .out on the
+/// end if it doesn't have one, so the original is never overwritten by
+/// accident.
+fn decompressed_name(input: &Path) -> PathBuf {
+    match input.file_name().and_then(strip_suffix) {
+        Some(stem) if !stem.is_empty() => input.with_file_name(stem),
+        _ => {
+            let mut name = input.as_os_str().to_owned();
+            name.push(".out");
+            PathBuf::from(name)
         }
+    }
+}
+
+/// Where output goes: stdout if asked for or if the input is stdin, then -o,
+/// then a name made up from the input's.
+fn output_path(
+    input: &Path,
+    output: &Option<PathBuf>,
+    stdout: bool,
+    default_name: fn(&Path) -> PathBuf,
+) -> PathBuf {
+    match output {
+        _ if stdout => PathBuf::from(STDIO),
+        Some(output) => output.clone(),
+        None if is_stdio(input) => PathBuf::from(STDIO),
+        None => default_name(input),
+    }
+}
+
+/// Opens the input, along with its length if it's a file. Stdin isn't
+/// seekable and its length isn't known up front, but compression only needs
+/// one pass over it.
+fn open_input(input: &Path) -> Result<(Box<dyn Read>, Option<u64>), HuffError> {
+    if is_stdio(input) {
+        return Ok((Box::new(io::stdin().lock()), None));
+    }
+    let file = File::open(input)?;
+    let len = file.metadata()?.len();
+    Ok((Box::new(BufReader::new(file)), Some(len)))
+}
+
+/// Runs `f` on the output, and deletes whatever it left behind if it fails.
+fn write_output<T, F>(output: &Path, f: F) -> Result<T, HuffError>
+where
+    F: FnOnce(&mut dyn Write) -> Result<T, HuffError>,
+{
+    if is_stdio(output) {
+        let mut stdout = io::stdout().lock();
+        return f(&mut stdout);
+    }
+
+    let result = File::create(output)
+        .map_err(HuffError::from)
+        .and_then(|file| f(&mut BufWriter::new(file)));
+    result.inspect_err(|_| {
+        let _ = fs::remove_file(output);
+    })
+}
+
+/// Passes everything through to `inner`, keeping count of the bytes.
+struct Counted<T> {
+    inner: T,
+    count: u64,
+}
+
+impl<T> Counted<T> {
+    fn new(inner: T) -> Self {
+        Counted { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How many bytes went into and came out of one file.
+#[derive(Default, Clone, Copy)]
+struct Sizes {
+    read: u64,
+    written: u64,
+}
+
+fn compress(
+    input: &Path,
+    output: &mut dyn Write,
+    encoder: &Encoder,
+) -> Result<(Sizes, CompressionReport), HuffError> {
+    let (reader, original_length) = open_input(input)?;
+    let mut reader = Counted::new(reader);
+
+    // goes through HuffWriter so only a block of the input is in memory at a time
+    let mut writer = HuffWriter::with_encoder(Counted::new(output), encoder.clone());
+    if let Some(original_length) = original_length {
+        writer = writer.original_length(original_length);
+    }
+    io::copy(&mut reader, &mut writer)?;
+    writer.try_finish()?;
+
+    // these go to stderr so they don't end up in the middle of piped output
+    let summary = writer.summary();
+    let blocks = summary.huffman_blocks
+        + summary.reused_blocks
+        + summary.adaptive_blocks
+        + summary.shared_blocks
+        + summary.stored_blocks;
+    if summary.stored() {
+        eprintln!(
+            "{}: stored, compressing would have made it bigger",
+            display_name(input)
+        );
+    } else if summary.stored_blocks > 0 {
+        eprintln!(
+            "{}: stored {} of {} blocks, compressing them would have made them bigger",
+            display_name(input),
+            summary.stored_blocks,
+            blocks
+        );
+    }
+
+    let sizes = Sizes {
+        read: reader.count,
+        written: writer.get_ref().count,
     };
+    Ok((sizes, summary.report))
+}
 
-    freq.count_chars(&file);
+fn decompress(input: &Path, output: &mut dyn Write, decoder: &Decoder) -> Result<Sizes, HuffError> {
+    let (reader, _) = open_input(input)?;
+    let mut reader = Counted::new(reader);
+    let mut output = Counted::new(output);
+    decoder.decode(&mut reader, &mut output)?;
+    Ok(Sizes {
+        read: reader.count,
+        written: output.count,
+    })
+}
 
-    let root = generate_tree(&freq);
+fn info(input: &Path) -> Result<(), HuffError> {
+    let (mut reader, _) = open_input(input)?;
+    let header = read_file_header(&mut reader)?;
 
-    let (header_size, header) = get_tree_header_with_size(&root);
+    let mut blocks = Vec::new();
+    while let Some(block) = read_block_info(&mut reader, &header)? {
+        blocks.push(block);
+    }
+    let original_size: u64 = blocks.iter().map(|block| block.original_size).sum();
+    // worked out from the blocks rather than the file, since stdin has no size
+    let mut header_bytes = Vec::new();
+    write_file_header(&mut header_bytes, &header)?;
+    let trailer_size = if header.checksum { 4 } else { 0 };
+    let compressed_size = header_bytes.len() as u64
+        + blocks
+            .iter()
+            .map(|block| block.compressed_size)
+            .sum::<u64>()
+        + 1
+        + trailer_size;
 
-    let prefix_table = generate_prefix_table(root);
+    let alphabet = match header.mode.alphabet {
+        Alphabet::Bytes => "bytes",
+        Alphabet::Chars => "chars",
+    };
+    let header_format = match header.mode.header {
+        HeaderFormat::Tree => "tree",
+        HeaderFormat::Canonical => "canonical",
+    };
+    let count = |block_type| {
+        blocks
+            .iter()
+            .filter(|block| block.block_type == block_type)
+            .count()
+    };
 
-    let _ = &file.seek(SeekFrom::Start(0));
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", display_name(input))?;
+    writeln!(out, "  format version:  {}", header.version)?;
+    writeln!(out, "  alphabet:        {}", alphabet)?;
+    writeln!(out, "  header:          {}", header_format)?;
+    writeln!(
+        out,
+        "  checksum:        {}",
+        if header.checksum { "crc-32" } else { "none" }
+    )?;
+    if let Some(fingerprint) = header.shared_tree {
+        writeln!(out, "  shared tree:     {:08x}", fingerprint)?;
+    }
+    writeln!(out, "  original size:   {} bytes", original_size)?;
+    if let Some(original_length) = header.original_length.filter(|&len| len != original_size) {
+        writeln!(out, "  (header says {} bytes)", original_length)?;
+    }
+    writeln!(out, "  compressed size: {} bytes", compressed_size)?;
+    if original_size > 0 {
+        writeln!(
+            out,
+            "  ratio:           {:.1}%",
+            compressed_size as f64 / original_size as f64 * 100.0
+        )?;
+    }
+    writeln!(
+        out,
+        "  blocks:          {} ({} huffman, {} reused, {} shared, {} adaptive, {} stored)",
+        blocks.len(),
+        count(BlockType::Huffman),
+        count(BlockType::Reuse),
+        count(BlockType::Shared),
+        count(BlockType::Adaptive),
+        count(BlockType::Stored)
+    )?;
+    Ok(())
+}
+
+/// How `compress --stats` prints the report.
+#[derive(Clone, Copy, PartialEq)]
+enum Stats {
+    None,
+    Text,
+    Json,
+}
+
+/// Prints `report` as a block of text or a line of JSON, all in one write so
+/// reports from different threads don't get mixed up.
+fn print_stats(
+    input: &Path,
+    report: &CompressionReport,
+    stats: Stats,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let name = display_name(input);
+    let text = match stats {
+        Stats::None => return Ok(()),
+        Stats::Text => [
+            name,
+            format!("  original size:   {} bytes", report.original_size),
+            format!(
+                "  compressed size: {} bytes ({} header, {} payload)",
+                report.compressed_size(),
+                report.header_bytes,
+                report.payload_bytes
+            ),
+            format!("  ratio:           {:.1}%", report.ratio() * 100.0),
+            format!("  entropy:         {:.3} bits/symbol", report.entropy),
+            format!(
+                "  average code:    {:.3} bits/symbol over {} symbols",
+                report.average_code_length(),
+                report.symbol_count
+            ),
+            format!("  padding:         {} bits", report.padding_bits),
+        ]
+        .join("\n"),
+        Stats::Json => {
+            let fields = [
+                ("file", json_string(&name)),
+                ("original_size", report.original_size.to_string()),
+                ("compressed_size", report.compressed_size().to_string()),
+                ("header_bytes", report.header_bytes.to_string()),
+                ("payload_bytes", report.payload_bytes.to_string()),
+                ("ratio", report.ratio().to_string()),
+                ("symbol_count", report.symbol_count.to_string()),
+                ("entropy", report.entropy.to_string()),
+                (
+                    "average_code_length",
+                    report.average_code_length().to_string(),
+                ),
+                ("padding_bits", report.padding_bits.to_string()),
+            ];
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(key, value)| format!("\"{}\":{}", key, value))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+    };
+    out.write_all(format!("{}\n", text).as_bytes())
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            c if (c as u32) < 0x20 => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
 
-    let data = get_encoded_data(&file, prefix_table);
+/// Counts the symbols in `input`.
+fn count_symbols<S: Symbol>(input: &Path) -> Result<Freq<S>, HuffError> {
+    let (reader, _) = open_input(input)?;
+    let mut freq: Freq<S> = Freq::new();
+    freq.count_chars(reader)?;
+    Ok(freq)
+}
 
-    let output_filename = &mut cli.file_name.unwrap().clone();
-    output_filename.push_str("_huff");
+/// Builds the tree `options` give the symbols in `input`, the same way a
+/// block with all of them in it would get its tree.
+fn build_tree<S: Symbol>(
+    input: &Path,
+    options: &TreeOptions,
+) -> Result<(Freq<S>, HuffmanTree<S>), HuffError> {
+    let freq = count_symbols::<S>(input)?;
+
+    let tree = HuffmanTree::with_max_length(&freq, options.max_code_length)?;
+    let tree = match options.header {
+        CliHeader::Tree => tree,
+        // the canonical header only keeps the code lengths, so the decoder
+        // ends up with the canonical layout of them rather than this tree
+        CliHeader::Canonical => {
+            HuffmanTree::from_code_lengths(&get_code_lengths(&tree.prefix_table()?), &freq)?
+        }
+    };
+    Ok((freq, tree))
+}
+
+fn tree(input: &Path, format: TreeFormat, options: &TreeOptions) -> Result<(), HuffError> {
+    let output = match (options.alphabet, format) {
+        (CliAlphabet::Bytes, TreeFormat::Dot) => {
+            build_tree::<u8>(input, options)?.1.to_dot().into_bytes()
+        }
+        (CliAlphabet::Chars, TreeFormat::Dot) => {
+            build_tree::<char>(input, options)?.1.to_dot().into_bytes()
+        }
+        (CliAlphabet::Bytes, TreeFormat::Htree) => {
+            shared_tree_bytes(&count_symbols::<u8>(input)?, options)?
+        }
+        (CliAlphabet::Chars, TreeFormat::Htree) => {
+            shared_tree_bytes(&count_symbols::<char>(input)?, options)?
+        }
+    };
+    io::stdout().lock().write_all(&output)?;
+    Ok(())
+}
+
+fn shared_tree_bytes<S: Symbol>(
+    freq: &Freq<S>,
+    options: &TreeOptions,
+) -> Result<Vec<u8>, HuffError> {
+    let shared_tree = SharedTree::from_freq(freq, options.header.into(), options.max_code_length)?;
+    let mut bytes = Vec::new();
+    shared_tree.save(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn codes(
+    input: &Path,
+    format: CodesFormat,
+    sort: CodesOrder,
+    options: &TreeOptions,
+) -> Result<(), HuffError> {
+    let text = match options.alphabet {
+        CliAlphabet::Bytes => format_codes::<u8>(input, format, sort, options)?,
+        CliAlphabet::Chars => format_codes::<char>(input, format, sort, options)?,
+    };
+    io::stdout().lock().write_all(text.as_bytes())?;
+    Ok(())
+}
+
+fn format_codes<S: Symbol>(
+    input: &Path,
+    format: CodesFormat,
+    sort: CodesOrder,
+    options: &TreeOptions,
+) -> Result<String, HuffError> {
+    let (freq, tree) = build_tree::<S>(input, options)?;
+    let mut entries = code_table(&freq, &tree.prefix_table()?);
+    if sort == CodesOrder::Code {
+        entries.sort_by_cached_key(CodeEntry::bits);
+    }
+
+    let rows: Vec<(String, u64, u8, String)> = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.symbol.escape(),
+                entry.count,
+                entry.length,
+                entry.bits(),
+            )
+        })
+        .collect();
+
+    let mut text = String::new();
+    match format {
+        CodesFormat::Table => {
+            let symbol_width = rows
+                .iter()
+                .map(|row| row.0.chars().count())
+                .fold(6, usize::max);
+            let count_width = rows
+                .iter()
+                .map(|row| row.1.to_string().len())
+                .fold(5, usize::max);
+            text += &format!(
+                "{:<symbol_width$}  {:>count_width$}  length  code\n",
+                "symbol", "count"
+            );
+            for (symbol, count, length, bits) in &rows {
+                text += &format!(
+                    "{:<symbol_width$}  {:>count_width$}  {:>6}  {}\n",
+                    symbol, count, length, bits
+                );
+            }
+        }
+        CodesFormat::Csv => {
+            text += "symbol,count,length,code\n";
+            for (symbol, count, length, bits) in &rows {
+                text += &format!("{},{},{},{}\n", csv_field(symbol), count, length, bits);
+            }
+        }
+        CodesFormat::Json => {
+            let objects: Vec<String> = rows
+                .iter()
+                .map(|(symbol, count, length, bits)| {
+                    format!(
+                        "  {{\"symbol\":{},\"count\":{},\"length\":{},\"code\":\"{}\"}}",
+                        json_string(symbol),
+                        count,
+                        length,
+                        bits
+                    )
+                })
+                .collect();
+            text += &match objects.is_empty() {
+                true => "[]\n".to_string(),
+                false => format!("[\n{}\n]\n", objects.join(",\n")),
+            };
+        }
+    }
+    Ok(text)
+}
+
+fn count(inputs: &[PathBuf], output: &Path, alphabet: CliAlphabet) -> Result<(), HuffError> {
+    match alphabet {
+        CliAlphabet::Bytes => save_counts(&count_files::<u8>(inputs)?, output),
+        CliAlphabet::Chars => save_counts(&count_files::<char>(inputs)?, output),
+    }
+}
+
+fn save_counts<S: Symbol>(freq: &Freq<S>, output: &Path) -> Result<(), HuffError> {
+    write_output(output, |writer| {
+        freq.save(&mut *writer)?;
+        Ok(writer.flush()?)
+    })
+    .map_err(|err| report(output, err))
+}
+
+fn train(
+    inputs: &[PathBuf],
+    counts: &[PathBuf],
+    output: &Path,
+    held_out: &[PathBuf],
+    smoothing: u64,
+    options: &TreeOptions,
+) -> Result<(), HuffError> {
+    match options.alphabet {
+        CliAlphabet::Bytes => train_symbols::<u8>(
+            inputs,
+            counts,
+            output,
+            held_out,
+            (smoothing, 0..=255),
+            options,
+        ),
+        CliAlphabet::Chars => train_symbols::<char>(
+            inputs,
+            counts,
+            output,
+            held_out,
+            (smoothing, (0..128u8).map(char::from)),
+            options,
+        ),
+    }
+}
+
+/// Counts the symbols in every file, trains a tree on them with `smoothing`
+/// added to each of the symbols that go with it, and saves the tree to `output`.
+fn train_symbols<S: Symbol>(
+    inputs: &[PathBuf],
+    counts: &[PathBuf],
+    output: &Path,
+    held_out: &[PathBuf],
+    smoothing: (u64, impl Iterator<Item = S>),
+    options: &TreeOptions,
+) -> Result<(), HuffError> {
+    let training = load_counts::<S>(counts)? + count_files::<S>(inputs)?;
+    let held_out_freq = match held_out.is_empty() {
+        true => None,
+        false => Some(count_files::<S>(held_out)?),
+    };
+
+    let mut smoothed = training.clone();
+    if smoothing.0 > 0 {
+        smoothed.smooth(smoothing.1, smoothing.0);
+    }
+    let shared_tree =
+        SharedTree::from_freq(&smoothed, options.header.into(), options.max_code_length)
+            .map_err(|err| report(output, err))?;
+    write_output(output, |writer| {
+        shared_tree.save(&mut *writer)?;
+        Ok(writer.flush()?)
+    })
+    .map_err(|err| report(output, err))?;
+
+    let prefix_table = shared_tree.prefix_table::<S>()?;
+    let mut out = io::stdout().lock();
+    let sources = match counts.len() {
+        0 => format!("{} files", inputs.len()),
+        n => format!("{} files and {} saved counts", inputs.len(), n),
+    };
+    writeln!(
+        out,
+        "{}: trained on {}, {} distinct symbols, fingerprint {:08x}",
+        output.display(),
+        sources,
+        training.counter.len(),
+        shared_tree.fingerprint()
+    )?;
+    print_fit(&mut out, "training set", &training, &prefix_table)?;
+    if let Some(freq) = held_out_freq {
+        print_fit(&mut out, "held out", &freq, &prefix_table)?;
+    }
+    Ok(())
+}
+
+/// Adds up the symbols in all of `inputs`.
+fn count_files<S: Symbol>(inputs: &[PathBuf]) -> Result<Freq<S>, HuffError> {
+    let mut freq: Freq<S> = Freq::new();
+    for input in inputs {
+        open_input(input)
+            .and_then(|(reader, _)| freq.count_chars(reader))
+            .map_err(|err| report(input, err))?;
+    }
+    Ok(freq)
+}
+
+/// Adds up the counts saved in all of `counts`.
+fn load_counts<S: Symbol>(counts: &[PathBuf]) -> Result<Freq<S>, HuffError> {
+    counts
+        .iter()
+        .map(|path| {
+            open_input(path)
+                .and_then(|(reader, _)| Freq::load(reader))
+                .map_err(|err| report(path, err))
+        })
+        .sum()
+}
+
+/// Prints how many bits a symbol from `freq` takes on average with
+/// `prefix_table`, next to the entropy, which is the best any table could do.
+fn print_fit<S: Symbol>(
+    out: &mut dyn Write,
+    label: &str,
+    freq: &Freq<S>,
+    prefix_table: &HashMap<S, (u32, u8)>,
+) -> io::Result<()> {
+    let (mut coded, mut bits, mut missing) = (0u64, 0u64, 0u64);
+    for (symbol, &count) in &freq.counter {
+        match prefix_table.get(symbol) {
+            Some(&(_, length)) => {
+                coded += count;
+                bits += count * length as u64;
+            }
+            None => missing += count,
+        }
+    }
+
+    let symbols = coded + missing;
+    if symbols == 0 {
+        return writeln!(out, "  {:<13} no symbols", format!("{}:", label));
+    }
+    writeln!(
+        out,
+        "  {:<13} {:.3} bits/symbol over {} symbols, entropy {:.3}",
+        format!("{}:", label),
+        bits as f64 / coded.max(1) as f64,
+        symbols,
+        freq.entropy()
+    )?;
+    if missing > 0 {
+        writeln!(
+            out,
+            "  {:<13} {} symbols ({:.2}%) have no code",
+            "",
+            missing,
+            missing as f64 / symbols as f64 * 100.0
+        )?;
+    }
+    Ok(())
+}
+
+/// Quotes `field` for CSV if it needs it.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Action<'a> {
+    Compress(&'a Encoder, Stats),
+    Decompress(&'a Decoder),
+    Test(&'a Decoder),
+}
+
+impl Action<'_> {
+    /// Whether a file found while walking a directory should be handled.
+    fn wants(&self, path: &Path) -> bool {
+        let compressed = has_suffix(path);
+        match self {
+            Action::Compress(..) => !compressed,
+            Action::Decompress(_) | Action::Test(_) => compressed,
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            Action::Compress(..) => "compressed",
+            Action::Decompress(_) => "decompressed",
+            Action::Test(_) => "tested",
+        }
+    }
+
+    /// Handles one file, writing to `output` (ignored when testing).
+    fn run(&self, input: &Path, output: &Path) -> Result<Sizes, HuffError> {
+        match self {
+            Action::Compress(encoder, stats) => {
+                let (sizes, report) =
+                    write_output(output, |writer| compress(input, writer, encoder))?;
+                // keep the stats out of the compressed data when that's on stdout
+                match is_stdio(output) {
+                    true => print_stats(input, &report, *stats, &mut io::stderr())?,
+                    false => print_stats(input, &report, *stats, &mut io::stdout())?,
+                }
+                Ok(sizes)
+            }
+            Action::Decompress(decoder) => {
+                write_output(output, |writer| decompress(input, writer, decoder))
+            }
+            Action::Test(decoder) => {
+                let sizes = decompress(input, &mut io::sink(), decoder)?;
+                writeln!(io::stdout(), "{}: ok", display_name(input))?;
+                Ok(sizes)
+            }
+        }
+    }
+}
+
+/// Turns the paths from the command line into a list of files. Directories are
+/// walked when `recursive` is set and are an error otherwise. Anything that goes
+/// wrong along the way is reported and added to `failures`.
+fn collect_files(
+    inputs: &[PathBuf],
+    recursive: bool,
+    action: Action,
+    failures: &mut Vec<HuffError>,
+) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if is_stdio(input) || !input.is_dir() {
+            files.push(input.clone());
+        } else if !recursive {
+            let err = io::Error::new(io::ErrorKind::IsADirectory, "is a directory, use -r");
+            failures.push(report(input, err.into()));
+        } else {
+            walk_dir(input, action, &mut files, failures);
+        }
+    }
+    files
+}
+
+fn walk_dir(dir: &Path, action: Action, files: &mut Vec<PathBuf>, failures: &mut Vec<HuffError>) {
+    let entries = match fs::read_dir(dir).and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
     {
-        let encoded_file = File::create(&output_filename).unwrap();
+        Ok(entries) => entries,
+        Err(err) => return failures.push(report(dir, err.into())),
+    };
+
+    let mut paths: Vec<PathBuf> = entries.iter().map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        // symlinks aren't followed, so a link back up the tree can't loop forever
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => walk_dir(&path, action, files, failures),
+            Ok(metadata) if metadata.is_file() && action.wants(&path) => files.push(path),
+            Ok(_) => {}
+            Err(err) => failures.push(report(&path, err.into())),
+        }
+    }
+}
+
+/// Calls `f` on every item, spread over `threads` threads, and returns the
+/// results in the same order as the items.
+fn run_parallel<T, R, F>(items: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(items.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.unwrap())
+        .collect()
+}
+
+/// Runs `action` over every file and reports how it went. Returns the error
+/// from the first file that failed, if any did.
+fn run_files(
+    inputs: &[PathBuf],
+    output: &Option<PathBuf>,
+    stdout: bool,
+    options: &FileOptions,
+    action: Action,
+) -> Result<(), HuffError> {
+    let mut failures = Vec::new();
+    let files = collect_files(inputs, options.recursive, action, &mut failures);
+
+    let default_name = match action {
+        Action::Compress(..) => compressed_name,
+        Action::Decompress(_) | Action::Test(_) => decompressed_name,
+    };
+    let threads = options
+        .threads
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+
+    let results = run_parallel(&files, threads, |input| {
+        let output = output_path(input, output, stdout, default_name);
+        action.run(input, &output).map_err(|err| report(input, err))
+    });
+
+    let mut total = Sizes::default();
+    let mut succeeded = 0;
+    for result in results {
+        match result {
+            Ok(sizes) => {
+                total.read += sizes.read;
+                total.written += sizes.written;
+                succeeded += 1;
+            }
+            Err(err) => failures.push(err),
+        }
+    }
+
+    if succeeded + failures.len() > 1 {
+        let mut summary = format!("huff: {} {} files", action.verb(), succeeded);
+        if succeeded > 0 && !matches!(action, Action::Test(_)) {
+            summary += &format!(", {} -> {} bytes", total.read, total.written);
+        }
+        if !failures.is_empty() {
+            summary += &format!(", {} failed", failures.len());
+        }
+        eprintln!("{}", summary);
+    }
+
+    match failures.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Prints what went wrong with `input`, and hands the error back.
+fn report(input: &Path, err: HuffError) -> HuffError {
+    // whatever we were writing to went away, like `huff info x | head`
+    if !matches!(&err, HuffError::Io(err) if err.kind() == io::ErrorKind::BrokenPipe) {
+        eprintln!("huff: {}: {}", display_name(input), err);
+    }
+    err
+}
+
+/// Catches the argument mixes clap can't: -, -c and -o write one file's worth
+/// of output, so they only go with a single input. -r is kept away from -c
+/// and -o by clap.
+fn check_args(command: &Command) -> Result<(), clap::Error> {
+    let (inputs, output, stdout) = match command {
+        Command::Compress {
+            inputs,
+            output,
+            stdout,
+            ..
+        }
+        | Command::Decompress {
+            inputs,
+            output,
+            stdout,
+            ..
+        } => (inputs, output, *stdout),
+        _ => return Ok(()),
+    };
+
+    let to_stdio = stdout || output.is_some() || inputs.iter().any(|input| is_stdio(input));
+    match inputs.len() > 1 && to_stdio {
+        true => Err(Cli::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "-, -c and -o only work with a single file",
+        )),
+        false => Ok(()),
+    }
+}
+
+fn run(command: &Command) -> Result<(), HuffError> {
+    match command {
+        Command::Compress {
+            inputs,
+            output,
+            stdout,
+            stats,
+            json,
+            files,
+            options,
+        } => {
+            let encoder = options.encoder()?;
+            let stats = match (*stats, *json) {
+                (_, true) => Stats::Json,
+                (true, false) => Stats::Text,
+                (false, false) => Stats::None,
+            };
+            let action = Action::Compress(&encoder, stats);
+            run_files(inputs, output, *stdout, files, action)
+        }
+        Command::Decompress {
+            inputs,
+            output,
+            stdout,
+            files,
+            options,
+        } => {
+            let decoder = options.decoder()?;
+            run_files(inputs, output, *stdout, files, Action::Decompress(&decoder))
+        }
+        Command::Test {
+            inputs,
+            files,
+            options,
+        } => {
+            let decoder = options.decoder()?;
+            run_files(inputs, &None, false, files, Action::Test(&decoder))
+        }
+        Command::Info { inputs } => {
+            let mut first_failure = None;
+            for input in inputs {
+                if let Err(err) = info(input) {
+                    first_failure.get_or_insert(report(input, err));
+                }
+            }
+            first_failure.map_or(Ok(()), Err)
+        }
+        Command::Tree {
+            input,
+            format,
+            options,
+        } => tree(input, *format, options).map_err(|err| report(input, err)),
+        Command::Codes {
+            input,
+            format,
+            sort,
+            options,
+        } => codes(input, *format, *sort, options).map_err(|err| report(input, err)),
+        Command::Count {
+            inputs,
+            output,
+            alphabet,
+        } => count(inputs, output, *alphabet),
+        Command::Train {
+            inputs,
+            counts,
+            output,
+            held_out,
+            smoothing,
+            options,
+        } => train(inputs, counts, output, held_out, *smoothing, options),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse().and_then(|cli| check_args(&cli.command).map(|_| cli)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            // --help and --version come through here too, and aren't failures
+            let _ = err.print();
+            return match err.use_stderr() {
+                true => ExitCode::from(USAGE_ERROR),
+                false => ExitCode::SUCCESS,
+            };
+        }
+    };
+
+    match run(&cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => ExitCode::from(exit_code(&err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_name() {
+        assert_eq!(
+            compressed_name(Path::new("dir/file.txt")),
+            Path::new("dir/file.txt_huff")
+        );
+    }
+
+    #[test]
+    fn test_decompressed_name() {
+        for (input, expected) in [
+            ("file.txt_huff", "file.txt"),
+            ("dir/file.txt_huff", "dir/file.txt"),
+            ("dir_huff/file", "dir_huff/file.out"),
+            ("file.txt", "file.txt.out"),
+            ("_huff", "_huff.out"),
+            ("dir/_huff", "dir/_huff.out"),
+        ] {
+            assert_eq!(decompressed_name(Path::new(input)), Path::new(expected));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = Path::new(OsStr::from_bytes(b"dir/caf\xe9"));
+        let compressed = compressed_name(name);
+
+        assert_eq!(compressed.as_os_str().as_bytes(), b"dir/caf\xe9_huff");
+        assert!(has_suffix(&compressed));
+        assert!(!has_suffix(name));
+        assert_eq!(decompressed_name(&compressed), name);
+    }
+
+    #[test]
+    fn test_has_suffix() {
+        assert!(has_suffix(Path::new("file_huff")));
+        assert!(has_suffix(Path::new("dir/file_huff")));
+        assert!(!has_suffix(Path::new("file_huff.txt")));
+        assert!(!has_suffix(Path::new("dir_huff/file")));
+    }
+
+    #[test]
+    fn test_output_path() {
+        let input = Path::new("file_huff");
+        let output = Some(PathBuf::from("out"));
+
+        assert_eq!(
+            output_path(input, &None, false, decompressed_name),
+            Path::new("file")
+        );
+        assert_eq!(
+            output_path(input, &output, false, decompressed_name),
+            Path::new("out")
+        );
+        assert_eq!(
+            output_path(input, &None, true, decompressed_name),
+            Path::new(STDIO)
+        );
+        assert_eq!(
+            output_path(Path::new(STDIO), &None, false, compressed_name),
+            Path::new(STDIO)
+        );
+        // -o wins over reading stdin
+        assert_eq!(
+            output_path(Path::new(STDIO), &output, false, compressed_name),
+            Path::new("out")
+        );
+    }
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        let cli = Cli::try_parse_from([&["huffman"], args].concat())?;
+        check_args(&cli.command)?;
+        Ok(cli)
+    }
+
+    #[test]
+    fn test_check_args() {
+        for args in [
+            &["compress", "a", "b"][..],
+            &["compress", "-c", "a"],
+            &["compress", "-o", "out", "a"],
+            &["compress", "-"],
+            &["compress", "-r", "dir"],
+            &["decompress", "a_huff", "b_huff"],
+            &["test", "a_huff", "b_huff"],
+        ] {
+            assert!(parse(args).is_ok(), "{:?}", args);
+        }
+
+        for args in [
+            &["compress", "-c", "a", "b"][..],
+            &["compress", "-o", "out", "a", "b"],
+            &["compress", "-", "a"],
+            &["decompress", "-c", "a_huff", "b_huff"],
+            &["compress", "-r", "-c", "dir"],
+            &["decompress", "-r", "-o", "out", "dir"],
+        ] {
+            let err = parse(args).err().unwrap();
+            assert_eq!(
+                err.kind(),
+                clap::error::ErrorKind::ArgumentConflict,
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_parallel_keeps_order() {
+        let items: Vec<u64> = (0..100).collect();
+
+        for threads in [1, 4, 200] {
+            // later items finish first
+            let results = run_parallel(&items, threads, |&item| {
+                thread::sleep(std::time::Duration::from_micros(100 - item));
+                item * 2
+            });
+
+            assert_eq!(
+                results,
+                items.iter().map(|item| item * 2).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("plain"), r#""plain""#);
+        assert_eq!(json_string(r#"a "b" \ c"#), r#""a \"b\" \\ c""#);
+        assert_eq!(json_string("\n\r\t"), r#""\n\r\t""#);
+        assert_eq!(json_string("\u{0}\u{1f}"), r#""\u0000\u001f""#);
+        assert_eq!(json_string("ü 字"), "\"ü 字\"");
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("a"), "a");
+        assert_eq!(csv_field("\\n"), "\\n");
+        assert_eq!(csv_field(","), "\",\"");
+        assert_eq!(csv_field("\""), "\"\"\"\"");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            HuffError::Io(io::Error::other("io")),
+            HuffError::EmptyInput,
+            HuffError::TruncatedHeader,
+            HuffError::TruncatedData,
+            HuffError::InvalidScalarValue(0),
+            HuffError::InvalidUtf8,
+            HuffError::InvalidMode(0),
+            HuffError::UnknownPrefix,
+            HuffError::UnknownSymbol(0),
+            HuffError::DuplicateSymbol(0),
+            HuffError::DuplicatePrefix {
+                prefix: 0,
+                length: 0,
+            },
+            HuffError::InvalidCodeLengths,
+            HuffError::CodeTooLong(0),
+            HuffError::MaxCodeLengthTooSmall(0),
+            HuffError::InvalidBit(0),
+            HuffError::TrailingByteMismatch(0),
+            HuffError::InvalidBlockType(0),
+            HuffError::NotHuff,
+            HuffError::UnsupportedVersion(0),
+            HuffError::LengthMismatch {
+                expected: 0,
+                actual: 0,
+            },
+            HuffError::ChecksumMismatch {
+                expected: 0,
+                actual: 0,
+            },
+            HuffError::NoPreviousTree,
+            HuffError::InvalidBlockSize(0),
+            HuffError::MissingSharedTree(0),
+            HuffError::SharedTreeMismatch {
+                expected: 0,
+                actual: 0,
+            },
+            HuffError::AlphabetMismatch {
+                expected: Alphabet::Bytes,
+                actual: Alphabet::Chars,
+            },
+        ];
+
+        let mut codes: Vec<u8> = errors.iter().map(exit_code).collect();
+        assert_eq!(codes[0], 2);
+        assert!(!codes.contains(&0) && !codes.contains(&USAGE_ERROR));
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
 
-        write_size_header(&encoded_file, header_size).unwrap();
-        write_compressed_data(&encoded_file, header).unwrap();
-        write_compressed_data(&encoded_file, data).unwrap();
+    #[test]
+    fn test_usage_errors_go_to_stderr() {
+        // main exits with USAGE_ERROR for these, and 0 for --help
+        assert!(parse(&["compress"]).err().unwrap().use_stderr());
+        assert!(parse(&["bogus"]).err().unwrap().use_stderr());
+        assert!(!parse(&["--help"]).err().unwrap().use_stderr());
     }
-    let mut compressed_file = File::open(output_filename).unwrap();
-    decompress_data(&mut compressed_file, String::from("decoded_file.txt")).unwrap();
 }
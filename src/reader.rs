@@ -0,0 +1,266 @@
+use std::io::{self, Read};
+
+use crate::codec::Decoder;
+use crate::encoding::crc32::Crc32;
+use crate::encoding::huffio::{check_trailer, decompress_next_block, read_file_header, FileHeader};
+use crate::error::HuffError;
+
+/// Decompresses `inner` as it's read from.
+///
+/// Blocks are decoded one at a time as the caller asks for more, so memory
+/// use is bounded by the size of a block rather than the whole input. Blocks
+/// are at most [`MAX_BLOCK_SIZE`](crate::MAX_BLOCK_SIZE) bytes, and
+/// [`BLOCK_SIZE`](crate::BLOCK_SIZE) unless the encoder was told otherwise.
+///
+/// ```
+/// use std::io::Read;
+/// use huffman::HuffReader;
+///
+/// let compressed = huffman::compress("a man a plan a canal panama".as_bytes())?;
+///
+/// let mut decompressed = String::new();
+/// HuffReader::new(compressed.as_slice()).read_to_string(&mut decompressed)?;
+///
+/// assert_eq!(decompressed, "a man a plan a canal panama");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct HuffReader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    // None until the file header has been read
+    header: Option<FileHeader>,
+    // the decoded block being handed out, and how much of it has been read
+    buffer: Vec<u8>,
+    pos: usize,
+    // total bytes decoded so far and their checksum, checked against the
+    // header and trailer at the end
+    decoded: u64,
+    checksum: Crc32,
+    // the header of the last huffman block, for blocks that reuse its tree
+    previous_header: Option<Vec<u8>>,
+    // the header of the shared tree, if the file header asks for one
+    shared_header: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl<R: Read> HuffReader<R> {
+    /// Wraps `inner` using the default [`Decoder`] settings.
+    pub fn new(inner: R) -> Self {
+        Self::with_decoder(inner, Decoder::new())
+    }
+
+    /// Wraps `inner`, decoding with the strategy set on `decoder`.
+    pub fn with_decoder(inner: R, decoder: Decoder) -> Self {
+        HuffReader {
+            inner,
+            decoder,
+            header: None,
+            buffer: Vec::new(),
+            pos: 0,
+            decoded: 0,
+            checksum: Crc32::new(),
+            previous_header: None,
+            shared_header: None,
+            done: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly will lose compressed data.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Gives back the inner reader, positioned after the last block that was
+    /// decoded. Anything decoded but not yet read is dropped.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// The file header, once the first read has gotten to it.
+    pub fn header(&self) -> Option<&FileHeader> {
+        self.header.as_ref()
+    }
+
+    /// The header of the shared tree `header` asks for, if it asks for one.
+    fn shared_header(&self, header: &FileHeader) -> Result<Option<Vec<u8>>, HuffError> {
+        let Some(expected) = header.shared_tree else {
+            return Ok(None);
+        };
+        match &self.decoder.shared_tree {
+            None => Err(HuffError::MissingSharedTree(expected)),
+            Some(shared_tree) if shared_tree.fingerprint() != expected => {
+                Err(HuffError::SharedTreeMismatch {
+                    expected,
+                    actual: shared_tree.fingerprint(),
+                })
+            }
+            Some(shared_tree) => Ok(Some(shared_tree.header.clone())),
+        }
+    }
+
+    /// Decodes the next block into the buffer, or marks the stream as done
+    /// when the end block comes up.
+    fn fill_buffer(&mut self) -> Result<(), HuffError> {
+        let header = match self.header {
+            Some(header) => header,
+            None => {
+                let header = read_file_header(&mut self.inner)?;
+                self.shared_header = self.shared_header(&header)?;
+                *self.header.insert(header)
+            }
+        };
+
+        self.buffer.clear();
+        self.pos = 0;
+        let strategy = self.decoder.strategy;
+        match decompress_next_block(
+            &mut self.inner,
+            &mut self.buffer,
+            &header,
+            strategy,
+            &mut self.previous_header,
+            self.shared_header.as_deref(),
+        )? {
+            Some(block_len) => {
+                self.decoded += block_len;
+                self.checksum.update(&self.buffer);
+            }
+            None => {
+                let checksum = self.checksum.value();
+                check_trailer(&mut self.inner, &header, self.decoded, checksum)?;
+                self.done = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for HuffReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() && !self.done {
+            self.fill_buffer()?;
+        }
+
+        let available = &self.buffer[self.pos..];
+        let len = buf.len().min(available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use crate::codec::{compress, Encoder};
+    use crate::encoding::decoding::DecodeStrategy;
+    use crate::encoding::symbol::Alphabet;
+    use crate::encoding::test_cases;
+    use crate::writer::{HuffWriter, BLOCK_SIZE};
+
+    use super::*;
+
+    #[test]
+    fn test_reader_round_trip() {
+        let input = test_cases::SAMPLE_TEST.as_bytes();
+        let compressed = compress(input).unwrap();
+
+        let mut decompressed = Vec::new();
+        HuffReader::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+
+        assert_eq!(decompressed, input);
+    }
+
+    #[test]
+    fn test_reader_memory_is_bounded_by_block() {
+        let input =
+            test_cases::CAPITAL_TEST.repeat(BLOCK_SIZE * 3 / test_cases::CAPITAL_TEST.len());
+        let mut writer = HuffWriter::new(Vec::new());
+        writer.write_all(input.as_bytes()).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        let mut reader = HuffReader::new(compressed.as_slice());
+        let mut decompressed = Vec::new();
+        let mut chunk = [0u8; 1000];
+        loop {
+            let len = reader.read(&mut chunk).unwrap();
+            if len == 0 {
+                break;
+            }
+            assert!(reader.buffer.len() <= BLOCK_SIZE);
+            decompressed.extend_from_slice(&chunk[..len]);
+        }
+
+        assert_eq!(decompressed, input.as_bytes());
+    }
+
+    #[test]
+    fn test_reader_io_copy() {
+        let input = "привет, мир! Les Misérables".repeat(100);
+        let encoder = Encoder::new().alphabet(Alphabet::Chars);
+        let compressed = encoder.encode_bytes(input.as_bytes()).unwrap();
+
+        let decoder = Decoder::new().strategy(DecodeStrategy::BitByBit);
+        let mut reader = HuffReader::with_decoder(Cursor::new(compressed), decoder);
+        let mut decompressed = Vec::new();
+        io::copy(&mut reader, &mut decompressed).unwrap();
+
+        assert_eq!(decompressed, input.as_bytes());
+    }
+
+    #[test]
+    fn test_reader_empty_stream() {
+        let compressed = HuffWriter::new(Vec::new()).finish().unwrap();
+
+        let mut decompressed = Vec::new();
+        HuffReader::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+
+        assert!(decompressed.is_empty());
+    }
+
+    #[test]
+    fn test_reader_stops_at_end_block() {
+        let mut compressed = compress(b"first").unwrap();
+        compressed.extend_from_slice(b"trailing");
+
+        let mut reader = HuffReader::new(compressed.as_slice());
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+
+        assert_eq!(decompressed, b"first");
+        assert_eq!(reader.into_inner(), b"trailing");
+    }
+
+    #[test]
+    fn test_reader_header() {
+        let compressed = compress(b"header").unwrap();
+        let mut reader = HuffReader::new(compressed.as_slice());
+        assert!(reader.header().is_none());
+
+        reader.read_to_end(&mut Vec::new()).unwrap();
+
+        assert_eq!(reader.header().unwrap().original_length, Some(6));
+    }
+
+    #[test]
+    fn test_reader_errors() {
+        let compressed = compress(test_cases::SAMPLE_TEST.as_bytes()).unwrap();
+        let truncated = &compressed[..compressed.len() - 1];
+
+        let err = HuffReader::new(truncated)
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+
+        assert!(matches!(HuffError::from(err), HuffError::TruncatedData));
+    }
+}
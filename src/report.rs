@@ -0,0 +1,101 @@
+/// Where the bytes of a compressed stream went, and how close the codes came
+/// to the best any symbol-by-symbol code could do.
+///
+/// Stored blocks count as payload, with every symbol costing its raw size.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CompressionReport {
+    /// How many bytes of input there were.
+    pub original_size: u64,
+    /// Everything that isn't the coded symbols themselves: the file header,
+    /// block headers, trees, the end marker and the checksum.
+    pub header_bytes: u64,
+    /// The coded symbols, including the padding at the end of each block.
+    pub payload_bytes: u64,
+    /// How many symbols the input was split into.
+    pub symbol_count: u64,
+    /// Bits spent on the symbols themselves, not counting padding.
+    pub code_bits: u64,
+    /// Zero bits added to fill out the last byte of each coded block.
+    pub padding_bits: u64,
+    /// Shannon entropy of the whole input in bits per symbol, the average
+    /// code length a perfect order-0 coder would get.
+    pub entropy: f64,
+}
+
+impl CompressionReport {
+    /// The whole size of the compressed stream.
+    pub fn compressed_size(&self) -> u64 {
+        self.header_bytes + self.payload_bytes
+    }
+
+    /// Compressed size over original size, so smaller is better. 0 for empty input.
+    pub fn ratio(&self) -> f64 {
+        if self.original_size == 0 {
+            return 0.0;
+        }
+        self.compressed_size() as f64 / self.original_size as f64
+    }
+
+    /// How many bits the average symbol was coded with.
+    pub fn average_code_length(&self) -> f64 {
+        if self.symbol_count == 0 {
+            return 0.0;
+        }
+        self.code_bits as f64 / self.symbol_count as f64
+    }
+}
+
+/// Shannon entropy in bits per symbol of a set of symbol counts.
+pub(crate) fn entropy(counts: impl Iterator<Item = u64> + Clone) -> f64 {
+    // u128 so counts near u64::MAX can't overflow the total
+    let total: u128 = counts.clone().map(u128::from).sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let total = total as f64;
+    counts
+        .filter(|&count| count > 0)
+        // p * log2(1 / p) rather than -p * log2(p), which gives -0 for a single symbol
+        .map(|count| count as f64 / total * (total / count as f64).log2())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy([5].into_iter()).to_string(), "0");
+        assert_eq!(entropy([1, 1].into_iter()), 1.0);
+        assert_eq!(entropy([2, 1, 1].into_iter()), 1.5);
+        assert_eq!(entropy(std::iter::empty()), 0.0);
+        assert_eq!(entropy([u64::MAX, u64::MAX].into_iter()), 1.0);
+    }
+
+    #[test]
+    fn test_ratio_and_average_code_length() {
+        let report = CompressionReport {
+            original_size: 100,
+            header_bytes: 10,
+            payload_bytes: 40,
+            symbol_count: 100,
+            code_bits: 318,
+            padding_bits: 2,
+            entropy: 3.1,
+        };
+
+        assert_eq!(report.compressed_size(), 50);
+        assert_eq!(report.ratio(), 0.5);
+        assert_eq!(report.average_code_length(), 3.18);
+    }
+
+    #[test]
+    fn test_empty_report() {
+        let report = CompressionReport::default();
+
+        assert_eq!(report.ratio(), 0.0);
+        assert_eq!(report.average_code_length(), 0.0);
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crate::codec::build_header;
+use crate::encoding::crc32::Crc32;
+use crate::encoding::frequency::Freq;
+use crate::encoding::huffio::{
+    read_block_header, read_prefix_table, write_size_header, HeaderFormat, Mode,
+};
+use crate::encoding::symbol::{Alphabet, Symbol};
+use crate::error::HuffError;
+
+/// Every shared tree file starts with these.
+const TREE_MAGIC: [u8; 4] = *b"HTRE";
+/// The shared tree file version written by this crate, and the only one it can read.
+const TREE_VERSION: u8 = 1;
+
+/// A tree that's kept once, outside of the compressed files coded with it.
+///
+/// Lots of small, similar inputs each spend more on their tree header than on
+/// their data. With a shared tree (see [`Encoder::shared_tree`]) the output
+/// only holds the tree's fingerprint, and [`Decoder::shared_tree`] has to be
+/// given the same tree to decode it.
+///
+/// ```
+/// use huffman::encoding::frequency::Freq;
+/// use huffman::{Decoder, Encoder, HeaderFormat, SharedTree};
+///
+/// let mut freq: Freq<u8> = Freq::new();
+/// freq.update(b"a man a plan a canal panama")?;
+/// let tree = SharedTree::from_freq(&freq, HeaderFormat::Canonical, 32)?;
+///
+/// let compressed = Encoder::new().shared_tree(tree.clone()).encode_bytes(b"a plan")?;
+/// let decompressed = Decoder::new().shared_tree(tree).decode_bytes(&compressed)?;
+///
+/// assert_eq!(decompressed, b"a plan");
+/// # Ok::<(), huffman::HuffError>(())
+/// ```
+///
+/// [`Encoder::shared_tree`]: crate::Encoder::shared_tree
+/// [`Decoder::shared_tree`]: crate::Decoder::shared_tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedTree {
+    pub(crate) mode: Mode,
+    pub(crate) header: Vec<u8>,
+}
+
+impl SharedTree {
+    /// Builds the tree a block holding the symbols counted in `freq` would get.
+    pub fn from_freq<S: Symbol>(
+        freq: &Freq<S>,
+        header: HeaderFormat,
+        max_code_length: u8,
+    ) -> Result<Self, HuffError> {
+        if freq.counter.is_empty() {
+            return Err(HuffError::EmptyInput);
+        }
+
+        let (header_bytes, _) = build_header(freq, header, max_code_length)?;
+        Ok(SharedTree {
+            mode: Mode {
+                alphabet: S::ALPHABET,
+                header,
+            },
+            header: header_bytes,
+        })
+    }
+
+    pub fn alphabet(&self) -> Alphabet {
+        self.mode.alphabet
+    }
+
+    pub fn header_format(&self) -> HeaderFormat {
+        self.mode.header
+    }
+
+    /// The code each symbol in the tree gets. `S` has to match [`SharedTree::alphabet`].
+    pub fn prefix_table<S: Symbol>(&self) -> Result<HashMap<S, (u32, u8)>, HuffError> {
+        read_prefix_table(self.mode.header, &self.header)
+    }
+
+    /// A CRC-32 of the tree. It goes in the file header of everything coded
+    /// with the tree, so the decoder can tell it has the right one.
+    pub fn fingerprint(&self) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&[self.mode.to_byte()]);
+        crc.update(&self.header);
+        crc.value()
+    }
+
+    /// Writes the tree as the magic bytes, a version byte and the mode byte,
+    /// then the header size and header the same way a huffman block has them.
+    pub fn save<W: Write>(&self, mut writer: W) -> Result<(), HuffError> {
+        writer.write_all(&TREE_MAGIC)?;
+        writer.write_all(&[TREE_VERSION, self.mode.to_byte()])?;
+        write_size_header(&mut writer, self.header.len() as u32)?;
+        writer.write_all(&self.header)?;
+        Ok(())
+    }
+
+    /// Reads a tree written by [`SharedTree::save`], and checks that there's a
+    /// valid code table in it.
+    pub fn load<R: Read>(mut reader: R) -> Result<Self, HuffError> {
+        let mut start = Vec::with_capacity(6);
+        (&mut reader).take(6).read_to_end(&mut start)?;
+        if start.is_empty() {
+            return Err(HuffError::EmptyInput);
+        }
+        if !start.starts_with(&TREE_MAGIC) {
+            return Err(HuffError::NotHuff);
+        }
+        let [_, _, _, _, version, mode] = start[..] else {
+            return Err(HuffError::TruncatedHeader);
+        };
+        if version != TREE_VERSION {
+            return Err(HuffError::UnsupportedVersion(version));
+        }
+
+        let mode = Mode::from_byte(mode)?;
+        let header = read_block_header(&mut reader)?;
+        match mode.alphabet {
+            Alphabet::Chars => {
+                read_prefix_table::<char>(mode.header, &header)?;
+            }
+            Alphabet::Bytes => {
+                read_prefix_table::<u8>(mode.header, &header)?;
+            }
+        }
+        Ok(SharedTree { mode, header })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::test_cases;
+
+    use super::*;
+
+    fn sample_tree<S: Symbol>(header: HeaderFormat) -> SharedTree {
+        let mut freq: Freq<S> = Freq::new();
+        freq.update(test_cases::SAMPLE_TEST.as_bytes()).unwrap();
+        SharedTree::from_freq(&freq, header, 32).unwrap()
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        for tree in [
+            sample_tree::<u8>(HeaderFormat::Canonical),
+            sample_tree::<u8>(HeaderFormat::Tree),
+            sample_tree::<char>(HeaderFormat::Canonical),
+        ] {
+            let mut saved = Vec::new();
+            tree.save(&mut saved).unwrap();
+
+            let loaded = SharedTree::load(saved.as_slice()).unwrap();
+
+            assert_eq!(loaded, tree);
+            assert_eq!(loaded.fingerprint(), tree.fingerprint());
+        }
+    }
+
+    #[test]
+    fn test_prefix_table() {
+        let mut freq: Freq<u8> = Freq::new();
+        freq.update(b"aaaabbc").unwrap();
+
+        let tree = SharedTree::from_freq(&freq, HeaderFormat::Canonical, 32).unwrap();
+        let prefix_table = tree.prefix_table::<u8>().unwrap();
+
+        assert_eq!(prefix_table[&b'a'].1, 1);
+        assert_eq!(prefix_table[&b'b'].1, 2);
+        assert_eq!(prefix_table[&b'c'].1, 2);
+    }
+
+    #[test]
+    fn test_fingerprint_covers_mode() {
+        let bytes = sample_tree::<u8>(HeaderFormat::Canonical);
+        let mut chars = bytes.clone();
+        chars.mode.alphabet = Alphabet::Chars;
+
+        assert_ne!(bytes.fingerprint(), chars.fingerprint());
+    }
+
+    #[test]
+    fn test_empty_freq() {
+        let freq: Freq<u8> = Freq::new();
+
+        let result = SharedTree::from_freq(&freq, HeaderFormat::Canonical, 32);
+
+        assert!(matches!(result, Err(HuffError::EmptyInput)));
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let mut saved = Vec::new();
+        sample_tree::<u8>(HeaderFormat::Canonical)
+            .save(&mut saved)
+            .unwrap();
+
+        assert!(matches!(
+            SharedTree::load(&[][..]),
+            Err(HuffError::EmptyInput)
+        ));
+        assert!(matches!(
+            SharedTree::load(&b"HUFF\x02\x00"[..]),
+            Err(HuffError::NotHuff)
+        ));
+        assert!(matches!(
+            SharedTree::load(&saved[..5]),
+            Err(HuffError::TruncatedHeader)
+        ));
+        assert!(matches!(
+            SharedTree::load(&saved[..saved.len() - 1]),
+            Err(HuffError::TruncatedHeader)
+        ));
+
+        let mut future = saved.clone();
+        future[4] = 2;
+        assert!(matches!(
+            SharedTree::load(future.as_slice()),
+            Err(HuffError::UnsupportedVersion(2))
+        ));
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::codec::{write_block, EncodeSummary, Encoder};
+use crate::encoding::crc32::Crc32;
+use crate::encoding::frequency::Freq;
+use crate::encoding::huffio::{
+    write_block_type, write_file_header, write_trailer, BlockType, FileHeader,
+};
+use crate::encoding::symbol::{Alphabet, Symbol};
+use crate::error::HuffError;
+use crate::report::entropy;
+use crate::shared_tree::SharedTree;
+
+/// How much input is compressed as one block, unless the [`Encoder`] says otherwise.
+pub const BLOCK_SIZE: usize = 1 << 20;
+/// The smallest block size an [`Encoder`] accepts.
+pub const MIN_BLOCK_SIZE: usize = 64 << 10;
+/// The biggest block size an [`Encoder`] accepts.
+pub const MAX_BLOCK_SIZE: usize = 4 << 20;
+
+/// Compresses everything written to it and passes the result on to `inner`.
+///
+/// Input is collected into blocks of [`Encoder::block_size`] bytes, and each
+/// block is written out as soon as it fills up, so memory use doesn't grow
+/// with the input. Call [`HuffWriter::finish`] when done to write
+/// the last block and the end of the stream. Dropping the writer finishes it
+/// too, but any error is lost that way.
+///
+/// ```
+/// use std::io::Write;
+/// use huffman::HuffWriter;
+///
+/// let mut writer = HuffWriter::new(Vec::new());
+/// writer.write_all("a man a plan a canal panama".as_bytes())?;
+/// let compressed = writer.finish()?;
+///
+/// assert_eq!(huffman::decompress(&compressed)?, "a man a plan a canal panama".as_bytes());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct HuffWriter<W: Write> {
+    // only None once finish() has handed it back
+    inner: Option<W>,
+    encoder: Encoder,
+    buffer: Vec<u8>,
+    original_length: Option<u64>,
+    written: u64,
+    checksum: Crc32,
+    summary: EncodeSummary,
+    // how often each symbol came up across every block, for the entropy
+    symbol_counts: HashMap<u32, u64>,
+    // the header of the last huffman block, so later blocks can reuse its tree
+    previous_header: Option<Vec<u8>>,
+    started: bool,
+    finished: bool,
+}
+
+impl<W: Write> HuffWriter<W> {
+    /// Wraps `inner` using the default [`Encoder`] settings.
+    pub fn new(inner: W) -> Self {
+        Self::with_encoder(inner, Encoder::new())
+    }
+
+    /// Wraps `inner`, compressing with the settings from `encoder`.
+    pub fn with_encoder(inner: W, encoder: Encoder) -> Self {
+        HuffWriter {
+            inner: Some(inner),
+            encoder,
+            buffer: Vec::new(),
+            original_length: None,
+            written: 0,
+            checksum: Crc32::new(),
+            summary: EncodeSummary::default(),
+            symbol_counts: HashMap::new(),
+            previous_header: None,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Records how long the input is going to be in the file header, so the
+    /// decoder can check it. Finishing fails if a different amount was written.
+    pub fn original_length(mut self, original_length: u64) -> Self {
+        self.original_length = Some(original_length);
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Writing to the inner writer directly will corrupt the output.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// What kind of blocks have been written so far, and a
+    /// [`CompressionReport`](crate::CompressionReport) for them. The report
+    /// only covers the whole stream once the writer is finished.
+    pub fn summary(&self) -> EncodeSummary {
+        let mut summary = self.summary;
+        summary.report.original_size = self.written;
+        summary.report.entropy = entropy(self.symbol_counts.values().copied());
+        summary
+    }
+
+    /// Writes whatever is left and the end of the stream, then returns the inner writer.
+    pub fn finish(mut self) -> Result<W, HuffError> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// Like [`HuffWriter::finish`], but keeps the writer around. Nothing more can
+    /// be written afterwards, and calling it again does nothing.
+    pub fn try_finish(&mut self) -> Result<(), HuffError> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.write_block()?;
+        // the last block left a partial character behind
+        if !self.buffer.is_empty() {
+            return Err(HuffError::InvalidUtf8);
+        }
+
+        if let Some(expected) = self.original_length.filter(|&len| len != self.written) {
+            return Err(HuffError::LengthMismatch {
+                expected,
+                actual: self.written,
+            });
+        }
+
+        self.start()?;
+        let mut end = Vec::new();
+        write_block_type(&mut end, BlockType::End)?;
+        write_trailer(&mut end, &self.file_header(), self.checksum.value())?;
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&end)?;
+        inner.flush()?;
+        self.summary.report.header_bytes += end.len() as u64;
+        self.finished = true;
+        Ok(())
+    }
+
+    fn file_header(&self) -> FileHeader {
+        let mut header = FileHeader::new(
+            self.encoder.mode(),
+            self.original_length,
+            self.encoder.checksum,
+        );
+        header.shared_tree = self
+            .encoder
+            .shared_tree
+            .as_ref()
+            .map(SharedTree::fingerprint);
+        header
+    }
+
+    fn start(&mut self) -> Result<(), HuffError> {
+        if !self.started {
+            self.encoder.check_block_size()?;
+            let mut header = Vec::new();
+            write_file_header(&mut header, &self.file_header())?;
+            self.inner.as_mut().unwrap().write_all(&header)?;
+            self.summary.report.header_bytes += header.len() as u64;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> Result<(), HuffError> {
+        match self.encoder.mode().alphabet {
+            Alphabet::Chars => self.write_symbols::<char>(),
+            Alphabet::Bytes => self.write_symbols::<u8>(),
+        }
+    }
+
+    /// Compresses the buffer as one block. A character split over the end of
+    /// the buffer stays behind and goes in the next block.
+    fn write_symbols<S: Symbol>(&mut self) -> Result<(), HuffError> {
+        let mut freq: Freq<S> = Freq::new();
+        freq.update(&self.buffer)?;
+        if freq.counter.is_empty() {
+            return Ok(());
+        }
+
+        self.start()?;
+        let complete = self.buffer.len() - freq.incomplete.len();
+        let inner = self.inner.as_mut().unwrap();
+        let block = write_block(
+            &freq,
+            &self.buffer[..complete],
+            inner,
+            &self.encoder,
+            &mut self.checksum,
+            &mut self.previous_header,
+        )?;
+        self.summary.add(block);
+        for (symbol, &count) in &freq.counter {
+            *self.symbol_counts.entry(symbol.to_bits()).or_insert(0) += count;
+            self.summary.report.symbol_count += count;
+        }
+        self.buffer.drain(..complete);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HuffWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("HuffWriter is already finished"));
+        }
+
+        // catches a bad block size before any of the input is buffered
+        self.start()?;
+
+        let block_size = self.encoder.block_size;
+        if self.buffer.len() >= block_size {
+            self.write_block()?;
+        }
+
+        let len = buf.len().min(block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    /// Writes out what's been buffered so far as a block of its own, then
+    /// flushes the inner writer. Flushing often makes for lots of small blocks,
+    /// which compress worse.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_block()?;
+        }
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for HuffWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::codec::decompress;
+    use crate::encoding::huffio::HeaderFormat;
+    use crate::encoding::test_cases;
+
+    use super::*;
+
+    fn compress_with(encoder: Encoder, input: &[u8]) -> Vec<u8> {
+        let mut writer = HuffWriter::with_encoder(Vec::new(), encoder);
+        writer.write_all(input).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_writer_round_trip() {
+        let input = test_cases::SAMPLE_TEST.as_bytes();
+
+        let compressed = compress_with(Encoder::new(), input);
+
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_writer_matches_encoder_for_one_block() {
+        let input = test_cases::CAPITAL_TEST.as_bytes();
+        let encoder = Encoder::new().header(HeaderFormat::Tree);
+
+        let from_writer = compress_with(encoder.clone(), input);
+        let from_encoder = encoder.encode_bytes(input).unwrap();
+
+        // the encoder's file header also has the length in it
+        let (from_writer, from_encoder) = (&from_writer[6..], &from_encoder[14..]);
+
+        // ties can be broken differently, so the trees may not match bit for bit
+        assert_eq!(from_writer.len(), from_encoder.len());
+        assert_eq!(from_writer[..5], from_encoder[..5]);
+    }
+
+    #[test]
+    fn test_writer_several_blocks() {
+        // three and a half blocks, with characters that straddle the block edges
+        let input = "añ€😀".repeat(BLOCK_SIZE * 7 / 2 / 10);
+
+        for alphabet in [Alphabet::Chars, Alphabet::Bytes] {
+            let compressed = compress_with(Encoder::new().alphabet(alphabet), input.as_bytes());
+
+            assert_eq!(decompress(&compressed).unwrap(), input.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_writer_flush_makes_a_block() {
+        let mut writer = HuffWriter::new(Vec::new());
+        writer.write_all(b"hello ").unwrap();
+        writer.flush().unwrap();
+        let flushed = writer.get_ref().len();
+        writer.write_all(b"world").unwrap();
+
+        let compressed = writer.finish().unwrap();
+
+        assert!(flushed > 0);
+        assert_eq!(decompress(&compressed).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn test_writer_empty() {
+        let compressed = HuffWriter::new(Vec::new()).finish().unwrap();
+
+        assert!(decompress(&compressed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_writer_finishes_on_drop() {
+        let mut compressed = Vec::new();
+        {
+            let mut writer = HuffWriter::new(&mut compressed);
+            writer.write_all(b"dropped").unwrap();
+        }
+
+        assert_eq!(decompress(&compressed).unwrap(), b"dropped");
+    }
+
+    #[test]
+    fn test_writer_rejects_partial_char() {
+        let mut writer =
+            HuffWriter::with_encoder(Vec::new(), Encoder::new().alphabet(Alphabet::Chars));
+        writer.write_all(&"é".as_bytes()[..1]).unwrap();
+
+        assert!(matches!(writer.try_finish(), Err(HuffError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn test_writer_errors_survive_io_copy() {
+        let encoder = Encoder::new().alphabet(Alphabet::Chars);
+        let mut writer = HuffWriter::with_encoder(Vec::new(), encoder);
+        let input = vec![0xff; BLOCK_SIZE + 1];
+
+        let err = io::copy(&mut Cursor::new(input), &mut writer).unwrap_err();
+
+        assert!(matches!(HuffError::from(err), HuffError::InvalidUtf8));
+    }
+
+    #[test]
+    fn test_writer_original_length() {
+        let input = test_cases::CAPITAL_TEST.as_bytes();
+        let mut writer = HuffWriter::new(Vec::new()).original_length(input.len() as u64);
+        writer.write_all(input).unwrap();
+
+        let compressed = writer.finish().unwrap();
+
+        // same file header as the encoder writes
+        let from_encoder = Encoder::new().encode_bytes(input).unwrap();
+        assert_eq!(compressed[..14], from_encoder[..14]);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_writer_original_length_mismatch() {
+        let mut writer = HuffWriter::new(Vec::new()).original_length(10);
+        writer.write_all(b"short").unwrap();
+
+        assert!(matches!(
+            writer.try_finish(),
+            Err(HuffError::LengthMismatch {
+                expected: 10,
+                actual: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn test_writer_after_finish() {
+        let mut writer = HuffWriter::new(Vec::new());
+        writer.try_finish().unwrap();
+
+        assert!(writer.write_all(b"late").is_err());
+    }
+
+    #[test]
+    fn test_writer_summary() {
+        let mut writer = HuffWriter::new(Vec::new());
+        writer
+            .write_all(test_cases::SAMPLE_TEST.as_bytes())
+            .unwrap();
+        writer.flush().unwrap();
+        writer.write_all(&[0, 1, 2, 3]).unwrap();
+        writer.try_finish().unwrap();
+
+        assert_eq!(writer.summary().huffman_blocks, 1);
+        assert_eq!(writer.summary().stored_blocks, 1);
+        assert!(!writer.summary().stored());
+
+        let compressed = writer.finish().unwrap();
+        let mut expected = test_cases::SAMPLE_TEST.as_bytes().to_vec();
+        expected.extend([0, 1, 2, 3]);
+        assert_eq!(decompress(&compressed).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_writer_report() {
+        for encoder in [
+            Encoder::new(),
+            Encoder::new().header(HeaderFormat::Tree).checksum(false),
+            Encoder::new().adaptive(true),
+        ] {
+            let input = test_cases::SAMPLE_TEST.as_bytes();
+            let mut writer = HuffWriter::with_encoder(Vec::new(), encoder);
+            writer.write_all(input).unwrap();
+            writer.try_finish().unwrap();
+
+            let report = writer.summary().report;
+            let compressed = writer.finish().unwrap();
+            assert_eq!(report.original_size, input.len() as u64);
+            assert_eq!(report.compressed_size(), compressed.len() as u64);
+            assert_eq!(report.symbol_count, input.len() as u64);
+            assert_eq!(
+                report.code_bits + report.padding_bits,
+                report.payload_bytes * 8
+            );
+            assert!(report.padding_bits < 8);
+            // nothing symbol by symbol beats the entropy
+            assert!(report.average_code_length() >= report.entropy);
+            assert!(report.entropy > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_writer_report_stored() {
+        let input = [0, 1, 2, 3];
+        let mut writer = HuffWriter::new(Vec::new());
+        writer.write_all(&input).unwrap();
+        writer.try_finish().unwrap();
+
+        let report = writer.summary().report;
+        assert_eq!(report.payload_bytes, 4);
+        assert_eq!(report.padding_bits, 0);
+        assert_eq!(report.average_code_length(), 8.0);
+        assert_eq!(report.entropy, 2.0);
+        assert_eq!(report.compressed_size(), writer.get_ref().len() as u64);
+    }
+
+    #[test]
+    fn test_writer_block_size() {
+        let input =
+            test_cases::SAMPLE_TEST.repeat(MIN_BLOCK_SIZE * 3 / test_cases::SAMPLE_TEST.len());
+        let encoder = Encoder::new().block_size(MIN_BLOCK_SIZE);
+
+        let mut writer = HuffWriter::with_encoder(Vec::new(), encoder);
+        writer.write_all(input.as_bytes()).unwrap();
+        writer.try_finish().unwrap();
+
+        let summary = writer.summary();
+        let blocks = input.len().div_ceil(MIN_BLOCK_SIZE) as u64;
+        assert_eq!(summary.huffman_blocks + summary.reused_blocks, blocks);
+        assert_eq!(
+            decompress(&writer.finish().unwrap()).unwrap(),
+            input.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_writer_rejects_block_size() {
+        let mut writer = HuffWriter::with_encoder(Vec::new(), Encoder::new().block_size(10));
+
+        let err = writer.write_all(b"abc").unwrap_err();
+
+        assert!(matches!(
+            HuffError::from(err),
+            HuffError::InvalidBlockSize(10)
+        ));
+    }
+
+    #[test]
+    fn test_writer_io_copy() {
+        let input = test_cases::SAMPLE_TEST.as_bytes();
+        let mut writer = HuffWriter::new(Vec::new());
+
+        io::copy(&mut Cursor::new(input), &mut writer).unwrap();
+
+        assert_eq!(decompress(&writer.finish().unwrap()).unwrap(), input);
+    }
+}